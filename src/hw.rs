@@ -1,10 +1,146 @@
 use anyhow::Result;
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::{self, Debug};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use circuit::Drive;
+use expect::Expectations;
+use timing::Latency;
+use trace::Tap;
+
+#[cfg(feature = "gpio-cdev")]
+pub mod cdev;
+pub mod circuit;
+pub mod clock;
+pub mod components;
+pub mod expect;
+pub mod fault;
+#[cfg(feature = "i2c-dev")]
+pub mod i2cdev;
+pub mod regmap;
+pub mod replay;
+mod rng;
+pub mod scan;
+pub mod shared_bus;
+pub mod spi_bus;
+pub mod spi_responder;
+#[cfg(feature = "spidev")]
+pub mod spidev;
+pub mod sysfs;
+pub mod timing;
+pub mod trace;
+pub mod vcd;
+pub mod waveform;
+
+#[cfg(feature = "gpio-cdev")]
+pub use cdev::CdevGpio;
+pub use circuit::{Circuit, Contention, NetState};
+pub use clock::{Clock, Delay, SystemClock, VirtualClock};
+pub use components::{Button, Led, Pir, Relay};
+pub use expect::{GpioExpectation, I2cExpectation, SpiExpectation};
+pub use fault::{Fault, FaultPlan, Trigger};
+#[cfg(feature = "i2c-dev")]
+pub use i2cdev::I2cDev;
+pub use regmap::{RegisterAccess, RegisterMap};
+pub use replay::{ReplayGpio, ReplayI2c, ReplaySession, ReplaySpi};
+pub use shared_bus::{BusMetrics, SharedI2c, SharedSpi, SharedSpiDevice};
+pub use spi_bus::{SpiBus, SpiDevice};
+pub use spi_responder::{CommandResponder, FnResponder};
+#[cfg(feature = "spidev")]
+pub use spidev::Spidev;
+pub use sysfs::SysfsGpio;
+pub use timing::BusTiming;
+pub use trace::{TraceEntry, TraceOp, TraceRecorder, Traced};
+pub use vcd::{LevelChange, VcdWriter};
+pub use waveform::Waveform;
+
+/// Function a GPIO pin is configured for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum PinMode {
+    Input,
+    Output,
+    /// Alternate function (ALT0-ALT5 on the BCM283x)
+    Alt(u8),
+}
+
+/// Internal pull resistor bias applied to a pin
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default, Serialize, Deserialize)]
+pub enum Pull {
+    #[default]
+    None,
+    Up,
+    Down,
+}
 
 /// GPIO abstraction trait for testable hardware interactions
 pub trait Gpio {
     fn write(&mut self, pin: u8, high: bool) -> Result<()>;
     fn read(&mut self, pin: u8) -> Result<bool>;
+    fn set_mode(&mut self, pin: u8, mode: PinMode) -> Result<()>;
+    fn set_pull(&mut self, pin: u8, pull: Pull) -> Result<()>;
+}
+
+/// Signal transition an edge subscription or event refers to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Edge {
+    Rising,
+    Falling,
+    /// Subscribe to both transitions (never reported on an event)
+    Both,
+}
+
+impl Edge {
+    /// Whether a subscription for this edge fires on the given transition
+    pub fn matches(self, rising: bool) -> bool {
+        match self {
+            Edge::Rising => rising,
+            Edge::Falling => !rising,
+            Edge::Both => true,
+        }
+    }
+}
+
+/// A timestamped level transition observed on an input pin
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EdgeEvent {
+    pub pin: u8,
+    /// `Edge::Rising` or `Edge::Falling`
+    pub edge: Edge,
+    /// Monotonic time of the transition
+    pub timestamp: Duration,
+}
+
+/// Edge-triggered event delivery for GPIO inputs
+pub trait GpioEvents: Gpio {
+    /// Start reporting `edge` transitions on `pin`
+    fn subscribe(&mut self, pin: u8, edge: Edge) -> Result<()>;
+    /// Stop reporting transitions on `pin`
+    fn unsubscribe(&mut self, pin: u8) -> Result<()>;
+    /// Block until an event arrives on any subscribed pin, or return `None` after `timeout`
+    fn wait_for_edge(&mut self, timeout: Duration) -> Result<Option<EdgeEvent>>;
+
+    /// Iterate over events that are already pending, without blocking
+    fn events(&mut self) -> PendingEvents<'_, Self>
+    where
+        Self: Sized,
+    {
+        PendingEvents { gpio: self }
+    }
+}
+
+/// Iterator over pending edge events, see [`GpioEvents::events`]
+pub struct PendingEvents<'a, G> {
+    gpio: &'a mut G,
+}
+
+impl<G: GpioEvents> Iterator for PendingEvents<'_, G> {
+    type Item = Result<EdgeEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.gpio.wait_for_edge(Duration::ZERO).transpose()
+    }
 }
 
 /// Mock GPIO implementation for testing and emulation
@@ -16,6 +152,23 @@ pub struct MockGpio {
     scripted_responses: HashMap<u8, Vec<bool>>,
     response_indices: HashMap<u8, usize>,
     failure_pins: Vec<u8>,
+    fault_plan: Option<FaultPlan>,
+    stuck_levels: HashMap<u8, bool>,
+    latency: Option<Latency>,
+    pin_modes: HashMap<u8, PinMode>,
+    pin_pulls: HashMap<u8, Pull>,
+    floating_reads: HashMap<u8, usize>,
+    input_levels: HashMap<u8, bool>,
+    observed_levels: HashMap<u8, bool>,
+    subscriptions: HashMap<u8, Edge>,
+    pending_events: VecDeque<EdgeEvent>,
+    level_changes: Vec<LevelChange>,
+    stimuli: Vec<Stimulus>,
+    /// Circuit the pins are wired into, and this mock's name in it
+    circuit: Option<(Circuit, String)>,
+    clock: Arc<dyn Clock>,
+    expectations: Option<Expectations<GpioExpectation>>,
+    trace: Option<Tap>,
 }
 
 impl MockGpio {
@@ -27,6 +180,64 @@ impl MockGpio {
             scripted_responses: HashMap::new(),
             response_indices: HashMap::new(),
             failure_pins: Vec::new(),
+            fault_plan: None,
+            stuck_levels: HashMap::new(),
+            latency: None,
+            pin_modes: HashMap::new(),
+            pin_pulls: HashMap::new(),
+            floating_reads: HashMap::new(),
+            input_levels: HashMap::new(),
+            observed_levels: HashMap::new(),
+            subscriptions: HashMap::new(),
+            pending_events: VecDeque::new(),
+            level_changes: Vec::new(),
+            stimuli: Vec::new(),
+            circuit: None,
+            clock: Arc::new(SystemClock::new()),
+            expectations: None,
+            trace: None,
+        }
+    }
+
+    /// Timestamp edge events and level changes with `clock`, e.g. a `VirtualClock`
+    pub fn set_clock(&mut self, clock: impl Clock + 'static) {
+        self.clock = Arc::new(clock);
+    }
+
+    /// Get the current time on the mock's clock
+    pub fn get_time(&self) -> Duration {
+        self.clock.now()
+    }
+
+    /// Wire this mock's pins into `circuit`, where `Circuit::connect` refers to them by `name`
+    pub fn set_circuit(&mut self, circuit: &Circuit, name: &str) {
+        self.circuit = Some((circuit.clone(), name.to_string()));
+        let mut pins: HashSet<u8> = self.pin_modes.keys().copied().collect();
+        pins.extend(self.pin_states.keys());
+        pins.extend(self.pin_pulls.keys());
+        for pin in pins {
+            self.update_circuit(pin);
+        }
+    }
+
+    /// Append every operation to a shared trace, under the name `source`
+    pub fn set_trace(&mut self, trace: &TraceRecorder, source: &str) {
+        self.trace = Some(Tap::new(trace, source));
+    }
+
+    /// Switch to strict mode: every call must match the next expectation
+    ///
+    /// Reads return the level given in their expectation instead of any
+    /// scripted or simulated level.
+    pub fn expect(&mut self, expectations: Vec<GpioExpectation>) {
+        self.expectations = Some(Expectations::new("GPIO", expectations));
+    }
+
+    /// Check that every expectation was met and nothing unexpected happened
+    pub fn done(&self) -> Result<()> {
+        match &self.expectations {
+            Some(expectations) => expectations.done().map_err(anyhow::Error::msg),
+            None => Ok(()),
         }
     }
 
@@ -41,6 +252,41 @@ impl MockGpio {
         self.failure_pins.push(pin);
     }
 
+    /// Inject faults into reads, writes and pin configuration as `plan` schedules
+    ///
+    /// Corrupting faults invert a read. Fails if the plan uses `Fault::FailWith`,
+    /// whose I2C errors mean nothing on a GPIO pin.
+    pub fn set_fault_plan(&mut self, plan: FaultPlan) -> Result<()> {
+        plan.check_bus("GPIO")?;
+        self.fault_plan = Some(plan);
+        Ok(())
+    }
+
+    pub fn get_fault_plan(&self) -> Option<&FaultPlan> {
+        self.fault_plan.as_ref()
+    }
+
+    /// Make every read, write and pin configuration take time on `clock`
+    pub fn set_latency(&mut self, clock: &VirtualClock, timing: BusTiming) {
+        self.latency = Some(Latency::new(clock, timing));
+    }
+
+    /// Get the total simulated time spent in operations
+    pub fn get_bus_time(&self) -> Duration {
+        self.latency.as_ref().map_or(Duration::ZERO, Latency::total)
+    }
+
+    /// Hold a pin at a level whatever drives it, like a shorted or damaged line
+    ///
+    /// Reads return `high`, and writes succeed but leave the pin at `high`.
+    pub fn set_stuck_at(&mut self, pin: u8, high: bool) {
+        self.stuck_levels.insert(pin, high);
+        if let Some(level) = self.pin_states.get_mut(&pin) {
+            *level = high;
+            self.update_circuit(pin);
+        }
+    }
+
     /// Get the number of write operations performed on a pin
     pub fn get_write_count(&self, pin: u8) -> usize {
         self.write_counts.get(&pin).copied().unwrap_or(0)
@@ -56,6 +302,88 @@ impl MockGpio {
         self.pin_states.get(&pin).copied()
     }
 
+    /// Get the mode a pin was configured with, if any
+    pub fn get_pin_mode(&self, pin: u8) -> Option<PinMode> {
+        self.pin_modes.get(&pin).copied()
+    }
+
+    /// Get the pull bias configured on a pin (`Pull::None` if never set)
+    pub fn get_pull(&self, pin: u8) -> Pull {
+        self.pin_pulls.get(&pin).copied().unwrap_or_default()
+    }
+
+    /// Get the number of reads of an input pin that was left floating
+    pub fn get_floating_read_count(&self, pin: u8) -> usize {
+        self.floating_reads.get(&pin).copied().unwrap_or(0)
+    }
+
+    /// Drive an input pin from outside, as a button or sensor would
+    pub fn set_input_level(&mut self, pin: u8, high: bool) {
+        self.end_waveform(pin);
+        self.input_levels.insert(pin, high);
+        self.record_level(pin, high);
+        self.observe_level(pin, high);
+    }
+
+    /// Drive an input pin with a signal that follows the mock's clock
+    ///
+    /// Reads return the waveform's level at the current time. Its changes
+    /// become edge events, stamped with the time they happened, when the
+    /// driver next reads or waits for an edge.
+    pub fn set_input_waveform(&mut self, pin: u8, waveform: Waveform) {
+        self.end_waveform(pin);
+        self.input_levels.remove(&pin);
+        let now = self.clock.now();
+        let level = waveform.level_at(now);
+        self.stimuli.push(Stimulus {
+            pin,
+            waveform,
+            from: now,
+            until: None,
+            synced: now,
+        });
+        self.observe_level_at(pin, level, now);
+    }
+
+    /// Get every level written or set from outside, in order, with its time
+    ///
+    /// Waveform changes are included up to the current time.
+    pub fn get_level_changes(&self) -> Vec<LevelChange> {
+        let now = self.clock.now();
+        let mut changes = self.level_changes.clone();
+        for stimulus in &self.stimuli {
+            let until = stimulus.until.unwrap_or(now);
+            changes.push(LevelChange {
+                at: stimulus.from,
+                pin: stimulus.pin,
+                high: stimulus.waveform.level_at(stimulus.from),
+            });
+            changes.extend(
+                stimulus
+                    .waveform
+                    .changes_between(stimulus.from, until)
+                    .into_iter()
+                    .map(|(at, high)| LevelChange {
+                        at,
+                        pin: stimulus.pin,
+                        high,
+                    }),
+            );
+        }
+        changes.sort_by_key(|change| change.at);
+        changes
+    }
+
+    /// Export the level changes so far as a VCD waveform
+    pub fn vcd(&self) -> VcdWriter {
+        VcdWriter::new(self.get_level_changes())
+    }
+
+    /// Get the number of edge events queued but not yet consumed
+    pub fn get_pending_event_count(&self) -> usize {
+        self.pending_events.len()
+    }
+
     /// Reset all counters and states
     pub fn reset(&mut self) {
         self.pin_states.clear();
@@ -64,32 +392,230 @@ impl MockGpio {
         self.scripted_responses.clear();
         self.response_indices.clear();
         self.failure_pins.clear();
+        self.fault_plan = None;
+        self.stuck_levels.clear();
+        self.pin_modes.clear();
+        self.pin_pulls.clear();
+        self.floating_reads.clear();
+        self.input_levels.clear();
+        self.observed_levels.clear();
+        self.subscriptions.clear();
+        self.pending_events.clear();
+        self.level_changes.clear();
+        self.stimuli.clear();
+        if let Some((circuit, name)) = &self.circuit {
+            circuit.release(name, self.clock.now());
+            circuit.take_changes(name);
+        }
+        self.expectations = None;
+    }
+
+    /// Count an operation against the fault plan and get the fault that hits it
+    fn next_fault(&mut self, reads_data: bool) -> Option<Fault> {
+        self.fault_plan.as_mut()?.next(reads_data)
+    }
+
+    /// Let the simulated time of an operation pass
+    fn charge(&mut self) {
+        if let Some(latency) = &mut self.latency {
+            latency.charge(0, None);
+        }
+    }
+
+    /// Error for an operation that the fault plan fails
+    fn injected_failure(&mut self, pin: u8) -> Option<anyhow::Error> {
+        let error = self
+            .next_fault(false)?
+            .error(&format!("GPIO pin {}", pin))?;
+        Some(error.into())
+    }
+
+    fn trace<T>(&self, op: TraceOp, result: &Result<T>) {
+        if let Some(tap) = &self.trace {
+            tap.record(op, result);
+        }
+    }
+
+    /// In strict mode, consume the expectation for a call that returns nothing
+    fn check_expected(&mut self, operation: GpioExpectation) -> Result<()> {
+        let Some(expectations) = &mut self.expectations else {
+            return Ok(());
+        };
+        expectations
+            .next(format!("{:?}", operation), |expected| {
+                (*expected == operation).then_some(())
+            })
+            .map_err(anyhow::Error::msg)
+    }
+
+    fn record_level(&mut self, pin: u8, high: bool) {
+        self.level_changes.push(LevelChange {
+            at: self.clock.now(),
+            pin,
+            high,
+        });
+    }
+
+    fn next_scripted_response(&mut self, pin: u8) -> Option<bool> {
+        let responses = self.scripted_responses.get(&pin)?;
+        let index = self.response_indices.get(&pin).copied().unwrap_or(0);
+        let response = responses.get(index).copied()?;
+        self.response_indices.insert(pin, index + 1);
+        Some(response)
+    }
+
+    /// What a pin does to a net it is wired to; pins written without a mode drive it
+    fn pin_drive(&self, pin: u8) -> Drive {
+        match (self.pin_modes.get(&pin), self.pin_states.get(&pin)) {
+            (Some(PinMode::Output), level) => Drive::Output(level.copied().unwrap_or(false)),
+            (None, Some(&level)) => Drive::Output(level),
+            _ => Drive::Input(self.get_pull(pin)),
+        }
+    }
+
+    fn update_circuit(&self, pin: u8) {
+        if let Some((circuit, name)) = &self.circuit {
+            circuit.drive(name, pin, self.pin_drive(pin), self.clock.now());
+        }
+    }
+
+    fn net_state(&self, pin: u8) -> Option<NetState> {
+        let (circuit, name) = self.circuit.as_ref()?;
+        circuit.state_at(name, pin)
+    }
+
+    /// Record and queue edge events for net changes on this mock's input pins
+    fn sync_circuit(&mut self) {
+        let Some((circuit, name)) = &self.circuit else {
+            return;
+        };
+        for (at, pin, state) in circuit.take_changes(name) {
+            if matches!(self.pin_drive(pin), Drive::Output(_)) {
+                continue;
+            }
+            if let Some(high) = state.level() {
+                self.level_changes.push(LevelChange { at, pin, high });
+                self.observe_level_at(pin, high, at);
+            }
+        }
+    }
+
+    fn active_waveform(&self, pin: u8) -> Option<&Waveform> {
+        self.stimuli
+            .iter()
+            .find(|stimulus| stimulus.pin == pin && stimulus.until.is_none())
+            .map(|stimulus| &stimulus.waveform)
+    }
+
+    /// Stop the waveform driving `pin`, after queueing its events so far
+    fn end_waveform(&mut self, pin: u8) {
+        self.sync_waveforms();
+        let now = self.clock.now();
+        for stimulus in &mut self.stimuli {
+            if stimulus.pin == pin && stimulus.until.is_none() {
+                stimulus.until = Some(now);
+            }
+        }
+    }
+
+    /// Queue edge events for waveform changes up to the current time
+    fn sync_waveforms(&mut self) {
+        let now = self.clock.now();
+        let mut changes = Vec::new();
+        for stimulus in &mut self.stimuli {
+            if stimulus.until.is_some() || stimulus.synced >= now {
+                continue;
+            }
+            for (at, high) in stimulus.waveform.changes_between(stimulus.synced, now) {
+                changes.push((at, stimulus.pin, high));
+            }
+            stimulus.synced = now;
+        }
+        changes.sort_by_key(|&(at, _, _)| at);
+        for (at, pin, high) in changes {
+            self.observe_level_at(pin, high, at);
+        }
+    }
+
+    fn observe_level(&mut self, pin: u8, high: bool) {
+        self.observe_level_at(pin, high, self.clock.now());
+    }
+
+    /// Track the externally observed level of a pin and queue an edge event on change
+    fn observe_level_at(&mut self, pin: u8, high: bool, at: Duration) {
+        let previous = self
+            .observed_levels
+            .insert(pin, high)
+            .unwrap_or(self.get_pull(pin) == Pull::Up);
+        if previous == high {
+            return;
+        }
+
+        if let Some(edge) = self.subscriptions.get(&pin) {
+            if edge.matches(high) {
+                self.pending_events.push_back(EdgeEvent {
+                    pin,
+                    edge: if high { Edge::Rising } else { Edge::Falling },
+                    timestamp: at,
+                });
+            }
+        }
     }
 }
 
+/// A waveform driving a `MockGpio` input from `from` until replaced
+#[derive(Debug, Clone)]
+struct Stimulus {
+    pin: u8,
+    waveform: Waveform,
+    from: Duration,
+    until: Option<Duration>,
+    /// Time up to which the waveform's changes have been queued as events
+    synced: Duration,
+}
+
 impl Default for MockGpio {
     fn default() -> Self {
         Self::new()
     }
 }
 
-impl Gpio for MockGpio {
-    fn write(&mut self, pin: u8, high: bool) -> Result<()> {
+impl MockGpio {
+    fn write_inner(&mut self, pin: u8, high: bool) -> Result<()> {
         if self.failure_pins.contains(&pin) {
             return Err(anyhow::anyhow!("Simulated GPIO failure on pin {}", pin));
         }
 
+        self.check_expected(GpioExpectation::Write(pin, high))?;
+
+        match self.pin_modes.get(&pin) {
+            Some(PinMode::Input) => {
+                return Err(anyhow::anyhow!("Cannot write to input pin {}", pin));
+            }
+            Some(PinMode::Alt(func)) => {
+                return Err(anyhow::anyhow!(
+                    "Cannot write to pin {} configured for ALT{}",
+                    pin,
+                    func
+                ));
+            }
+            _ => {}
+        }
+
         // Increment write counter
         let count = self.write_counts.entry(pin).or_insert(0);
         *count += 1;
 
-        // Update pin state
+        // Update pin state, unless it is stuck
+        let high = self.stuck_levels.get(&pin).copied().unwrap_or(high);
         self.pin_states.insert(pin, high);
+        self.record_level(pin, high);
+        self.update_circuit(pin);
 
         Ok(())
     }
 
-    fn read(&mut self, pin: u8) -> Result<bool> {
+    fn read_inner(&mut self, pin: u8) -> Result<bool> {
         if self.failure_pins.contains(&pin) {
             return Err(anyhow::anyhow!("Simulated GPIO failure on pin {}", pin));
         }
@@ -97,86 +623,470 @@ impl Gpio for MockGpio {
         // Increment read counter
         let count = self.read_counts.entry(pin).or_insert(0);
         *count += 1;
+        self.sync_waveforms();
+        self.sync_circuit();
+
+        if let Some(expectations) = &mut self.expectations {
+            let level = expectations
+                .next(format!("Read({}, _)", pin), |expected| match *expected {
+                    GpioExpectation::Read(p, level) if p == pin => Some(level),
+                    _ => None,
+                })
+                .map_err(anyhow::Error::msg)?;
+            self.observe_level(pin, level);
+            return Ok(level);
+        }
+
+        if let Some(&level) = self.stuck_levels.get(&pin) {
+            return Ok(level);
+        }
 
         // Check if we have scripted responses
-        if let Some(responses) = self.scripted_responses.get(&pin) {
-            let index = self.response_indices.get(&pin).copied().unwrap_or(0);
-            if index < responses.len() {
-                let response = responses[index];
-                self.response_indices.insert(pin, index + 1);
-                return Ok(response);
+        if let Some(response) = self.next_scripted_response(pin) {
+            self.observe_level(pin, response);
+            return Ok(response);
+        }
+
+        // Externally driven level, unless the pin is driving itself
+        if self.pin_modes.get(&pin) != Some(&PinMode::Output) {
+            match self.net_state(pin) {
+                Some(NetState::Contention) => {
+                    return Err(anyhow::anyhow!(
+                        "Pin {} is on a net with contending outputs",
+                        pin
+                    ));
+                }
+                Some(state) => {
+                    if let Some(level) = state.level() {
+                        return Ok(level);
+                    }
+                }
+                None => {}
+            }
+            if let Some(waveform) = self.active_waveform(pin) {
+                return Ok(waveform.level_at(self.clock.now()));
             }
+            if let Some(level) = self.input_levels.get(&pin) {
+                return Ok(*level);
+            }
+        }
+
+        // Undriven inputs settle to their pull level, or float without one
+        if self.pin_modes.get(&pin) == Some(&PinMode::Input) {
+            return match self.get_pull(pin) {
+                Pull::Up => Ok(true),
+                Pull::Down => Ok(false),
+                Pull::None => {
+                    log::warn!("Read of floating input pin {} (no pull configured)", pin);
+                    *self.floating_reads.entry(pin).or_insert(0) += 1;
+                    Ok(false)
+                }
+            };
         }
 
         // Default to current pin state or false if not set
         Ok(self.pin_states.get(&pin).copied().unwrap_or(false))
     }
+
+    fn set_mode_inner(&mut self, pin: u8, mode: PinMode) -> Result<()> {
+        if self.failure_pins.contains(&pin) {
+            return Err(anyhow::anyhow!("Simulated GPIO failure on pin {}", pin));
+        }
+        self.check_expected(GpioExpectation::SetMode(pin, mode))?;
+
+        self.pin_modes.insert(pin, mode);
+        self.update_circuit(pin);
+        Ok(())
+    }
+
+    fn set_pull_inner(&mut self, pin: u8, pull: Pull) -> Result<()> {
+        if self.failure_pins.contains(&pin) {
+            return Err(anyhow::anyhow!("Simulated GPIO failure on pin {}", pin));
+        }
+        self.check_expected(GpioExpectation::SetPull(pin, pull))?;
+
+        self.pin_pulls.insert(pin, pull);
+        self.update_circuit(pin);
+        Ok(())
+    }
 }
 
+impl Gpio for MockGpio {
+    fn write(&mut self, pin: u8, high: bool) -> Result<()> {
+        self.charge();
+        let result = match self.injected_failure(pin) {
+            Some(error) => Err(error),
+            None => self.write_inner(pin, high),
+        };
+        self.trace(TraceOp::GpioWrite { pin, high }, &result);
+        result
+    }
+
+    fn read(&mut self, pin: u8) -> Result<bool> {
+        self.charge();
+        let fault = self.next_fault(true);
+        let result = match fault
+            .as_ref()
+            .and_then(|fault| fault.error(&format!("GPIO pin {}", pin)))
+        {
+            Some(error) => Err(error.into()),
+            None => self.read_inner(pin).map(|high| match fault {
+                // A pin is a single bit, so any number of flips inverts it
+                Some(Fault::FlipBits(flips)) => high ^ (flips > 0),
+                _ => high,
+            }),
+        };
+        let high = *result.as_ref().unwrap_or(&false);
+        self.trace(TraceOp::GpioRead { pin, high }, &result);
+        result
+    }
+
+    fn set_mode(&mut self, pin: u8, mode: PinMode) -> Result<()> {
+        self.charge();
+        let result = match self.injected_failure(pin) {
+            Some(error) => Err(error),
+            None => self.set_mode_inner(pin, mode),
+        };
+        self.trace(TraceOp::GpioSetMode { pin, mode }, &result);
+        result
+    }
+
+    fn set_pull(&mut self, pin: u8, pull: Pull) -> Result<()> {
+        self.charge();
+        let result = match self.injected_failure(pin) {
+            Some(error) => Err(error),
+            None => self.set_pull_inner(pin, pull),
+        };
+        self.trace(TraceOp::GpioSetPull { pin, pull }, &result);
+        result
+    }
+}
+
+impl GpioEvents for MockGpio {
+    fn subscribe(&mut self, pin: u8, edge: Edge) -> Result<()> {
+        if self.failure_pins.contains(&pin) {
+            return Err(anyhow::anyhow!("Simulated GPIO failure on pin {}", pin));
+        }
+
+        if self.pin_modes.get(&pin) == Some(&PinMode::Output) {
+            return Err(anyhow::anyhow!(
+                "Cannot subscribe to edges on output pin {}",
+                pin
+            ));
+        }
+
+        self.subscriptions.insert(pin, edge);
+        Ok(())
+    }
+
+    fn unsubscribe(&mut self, pin: u8) -> Result<()> {
+        self.subscriptions.remove(&pin);
+        self.pending_events.retain(|event| event.pin != pin);
+        Ok(())
+    }
+
+    /// The mock never sleeps: waveform changes up to the clock's current time
+    /// are queued first, then when nothing is pending it advances the scripted
+    /// responses of subscribed pins until one produces an event, and otherwise
+    /// reports a timeout immediately.
+    fn wait_for_edge(&mut self, _timeout: Duration) -> Result<Option<EdgeEvent>> {
+        self.sync_waveforms();
+        self.sync_circuit();
+        let mut pins: Vec<u8> = self.subscriptions.keys().copied().collect();
+        pins.sort_unstable();
+
+        while self.pending_events.is_empty() {
+            let mut advanced = false;
+            for &pin in &pins {
+                if let Some(level) = self.next_scripted_response(pin) {
+                    self.observe_level(pin, level);
+                    advanced = true;
+                }
+            }
+            if !advanced {
+                break;
+            }
+        }
+
+        let event = self.pending_events.pop_front();
+        if let (Some(tap), Some(event)) = (&self.trace, &event) {
+            tap.record_event(TraceOp::GpioEdge {
+                pin: event.pin,
+                edge: event.edge,
+            });
+        }
+        Ok(event)
+    }
+}
+
+/// One step of a combined I2C transaction
+#[derive(Debug)]
+pub enum I2cOperation<'a> {
+    Write(&'a [u8]),
+    Read(&'a mut [u8]),
+}
+
+/// Recorded step of a combined I2C transaction, with the bytes that were read
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum I2cOpRecord {
+    Write(Vec<u8>),
+    Read(Vec<u8>),
+}
+
+/// Bus-level failure of an I2C transfer
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum I2cError {
+    /// No device acknowledged the address (nothing present at it)
+    AddressNack(u8),
+    /// The device acknowledged its address but not a data byte
+    DataNack(u8),
+    /// Another controller won arbitration for the bus
+    ArbitrationLoss,
+    /// The transfer did not complete in time (e.g. clock stretched too long)
+    Timeout,
+    Other(String),
+}
+
+impl fmt::Display for I2cError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            I2cError::AddressNack(address) => {
+                write!(f, "No ACK from I2C address 0x{:02X}", address)
+            }
+            I2cError::DataNack(address) => {
+                write!(f, "I2C address 0x{:02X} NACKed a data byte", address)
+            }
+            I2cError::ArbitrationLoss => write!(f, "I2C arbitration lost"),
+            I2cError::Timeout => write!(f, "I2C bus timeout"),
+            I2cError::Other(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for I2cError {}
+
+pub type I2cResult<T> = std::result::Result<T, I2cError>;
+
 /// I2C abstraction trait for expandable hardware interfaces
 pub trait I2c {
-    fn write(&mut self, address: u8, data: &[u8]) -> Result<()>;
-    fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<usize>;
+    fn write(&mut self, address: u8, data: &[u8]) -> I2cResult<()>;
+    fn read(&mut self, address: u8, buffer: &mut [u8]) -> I2cResult<usize>;
+    /// Run operations back to back with repeated starts and a single STOP
+    fn transaction(&mut self, address: u8, operations: &mut [I2cOperation<'_>]) -> I2cResult<()>;
+
+    /// Write then read without a STOP in between, e.g. to read from a register pointer
+    fn write_read(&mut self, address: u8, data: &[u8], buffer: &mut [u8]) -> I2cResult<()> {
+        self.transaction(
+            address,
+            &mut [I2cOperation::Write(data), I2cOperation::Read(buffer)],
+        )
+    }
+}
+
+/// Simulated chip that answers for one address on a `MockI2c`
+pub trait I2cDevice: Debug + Send {
+    /// Handle bytes the controller wrote to the device
+    fn write(&mut self, data: &[u8]) -> I2cResult<()>;
+    /// Fill `buffer` with the bytes the device drives onto the bus
+    fn read(&mut self, buffer: &mut [u8]) -> I2cResult<()>;
 }
 
 /// Mock I2C implementation for testing
+///
+/// Only addresses that were registered (with `add_address`, a scripted
+/// response or an attached device) acknowledge; everything else NACKs.
+/// Clones share attached devices.
 #[derive(Debug, Clone)]
 pub struct MockI2c {
     write_log: Vec<(u8, Vec<u8>)>,
+    transaction_log: Vec<(u8, Vec<I2cOpRecord>)>,
+    devices: HashMap<u8, Arc<Mutex<dyn I2cDevice>>>,
     read_responses: HashMap<u8, Vec<u8>>,
-    failure_addresses: Vec<u8>,
+    write_read_responses: HashMap<(u8, Vec<u8>), Vec<u8>>,
+    last_writes: HashMap<u8, Vec<u8>>,
+    present_addresses: HashSet<u8>,
+    failure_addresses: HashMap<u8, I2cError>,
+    fault_plan: Option<FaultPlan>,
+    latency: Option<Latency>,
+    expectations: Option<Expectations<I2cExpectation>>,
+    trace: Option<Tap>,
 }
 
 impl MockI2c {
     pub fn new() -> Self {
         Self {
             write_log: Vec::new(),
+            transaction_log: Vec::new(),
+            devices: HashMap::new(),
             read_responses: HashMap::new(),
-            failure_addresses: Vec::new(),
+            write_read_responses: HashMap::new(),
+            last_writes: HashMap::new(),
+            present_addresses: HashSet::new(),
+            failure_addresses: HashMap::new(),
+            fault_plan: None,
+            latency: None,
+            expectations: None,
+            trace: None,
         }
     }
 
+    /// Append every operation to a shared trace, under the name `source`
+    pub fn set_trace(&mut self, trace: &TraceRecorder, source: &str) {
+        self.trace = Some(Tap::new(trace, source));
+    }
+
+    /// Make every transfer take time on `clock`, e.g. `BusTiming::i2c(100_000)`
+    ///
+    /// Each write, read and transaction segment clocks an address byte before its data.
+    pub fn set_latency(&mut self, clock: &VirtualClock, timing: BusTiming) {
+        self.latency = Some(Latency::new(clock, timing));
+    }
+
+    /// Get the total simulated time spent on the bus
+    pub fn get_bus_time(&self) -> Duration {
+        self.latency.as_ref().map_or(Duration::ZERO, Latency::total)
+    }
+
+    /// Switch to strict mode: every call must match the next expectation
+    ///
+    /// Expectations replace address registration, devices and scripted
+    /// responses: reads return the data given in their expectation. Errors
+    /// from `set_address_error` still apply, failing a call before it is
+    /// matched, so a strict test can script a NACK.
+    pub fn expect(&mut self, expectations: Vec<I2cExpectation>) {
+        self.expectations = Some(Expectations::new("I2C", expectations));
+    }
+
+    /// Check that every expectation was met and nothing unexpected happened
+    pub fn done(&self) -> Result<()> {
+        match &self.expectations {
+            Some(expectations) => expectations.done().map_err(anyhow::Error::msg),
+            None => Ok(()),
+        }
+    }
+
+    /// Make `address` acknowledge; reads return zeros unless a response is scripted
+    pub fn add_address(&mut self, address: u8) {
+        self.present_addresses.insert(address);
+    }
+
+    /// Attach a simulated device, which then handles all traffic to `address`
+    ///
+    /// The returned handle lets tests inspect or poke the device afterwards.
+    pub fn attach_device<D: I2cDevice + 'static>(
+        &mut self,
+        address: u8,
+        device: D,
+    ) -> Arc<Mutex<D>> {
+        let device = Arc::new(Mutex::new(device));
+        self.devices.insert(address, device.clone());
+        self.add_address(address);
+        device
+    }
+
     pub fn set_read_response(&mut self, address: u8, data: Vec<u8>) {
         self.read_responses.insert(address, data);
+        self.add_address(address);
+    }
+
+    /// Answer reads from `address` with `data` when the preceding write was `written`
+    pub fn set_write_read_response(&mut self, address: u8, written: &[u8], data: Vec<u8>) {
+        self.write_read_responses
+            .insert((address, written.to_vec()), data);
+        self.add_address(address);
     }
 
     pub fn set_address_failure(&mut self, address: u8) {
-        self.failure_addresses.push(address);
+        self.set_address_error(
+            address,
+            I2cError::Other(format!(
+                "Simulated I2C failure on address 0x{:02X}",
+                address
+            )),
+        );
+    }
+
+    /// Make every transfer to `address` fail with a specific bus error
+    pub fn set_address_error(&mut self, address: u8, error: I2cError) {
+        self.failure_addresses.insert(address, error);
+    }
+
+    /// Inject faults into writes, reads and transactions as `plan` schedules
+    pub fn set_fault_plan(&mut self, plan: FaultPlan) {
+        self.fault_plan = Some(plan);
+    }
+
+    pub fn get_fault_plan(&self) -> Option<&FaultPlan> {
+        self.fault_plan.as_ref()
+    }
+
+    /// Count an operation against the fault plan and get the fault that hits it
+    fn next_fault(&mut self, reads_data: bool) -> Option<Fault> {
+        self.fault_plan.as_mut()?.next(reads_data)
     }
 
+    /// Let the simulated time of a transfer of `bytes`, address bytes included, pass
+    fn charge(&mut self, bytes: usize) {
+        if let Some(latency) = &mut self.latency {
+            latency.charge(bytes, None);
+        }
+    }
+
+    fn corrupt(&mut self, buffers: &mut [&mut [u8]], fault: Option<&Fault>) {
+        if let (Some(plan), Some(Fault::FlipBits(flips))) = (&mut self.fault_plan, fault) {
+            plan.corrupt(buffers, *flips);
+        }
+    }
+
+    /// Get plain writes; writes inside a transaction are in the transaction log
     pub fn get_write_log(&self) -> &[(u8, Vec<u8>)] {
         &self.write_log
     }
-}
 
-impl Default for MockI2c {
-    fn default() -> Self {
-        Self::new()
+    /// Get each combined transaction as a single entry
+    pub fn get_transaction_log(&self) -> &[(u8, Vec<I2cOpRecord>)] {
+        &self.transaction_log
     }
-}
 
-impl I2c for MockI2c {
-    fn write(&mut self, address: u8, data: &[u8]) -> Result<()> {
-        if self.failure_addresses.contains(&address) {
-            return Err(anyhow::anyhow!(
-                "Simulated I2C failure on address 0x{:02X}",
-                address
-            ));
+    /// Fail with the error scripted for `address`, if any
+    fn check_address_error(&self, address: u8) -> I2cResult<()> {
+        match self.failure_addresses.get(&address) {
+            Some(error) => Err(error.clone()),
+            None => Ok(()),
         }
+    }
 
-        self.write_log.push((address, data.to_vec()));
+    /// NACK addresses nothing was registered at
+    fn check_present(&self, address: u8) -> I2cResult<()> {
+        if !self.present_addresses.contains(&address) {
+            return Err(I2cError::AddressNack(address));
+        }
         Ok(())
     }
 
-    fn read(&mut self, address: u8, buffer: &mut [u8]) -> Result<usize> {
-        if self.failure_addresses.contains(&address) {
-            return Err(anyhow::anyhow!(
-                "Simulated I2C failure on address 0x{:02X}",
-                address
-            ));
+    /// Hand written bytes to an attached device and remember them for keyed responses
+    fn deliver(&mut self, address: u8, data: &[u8]) -> I2cResult<()> {
+        if let Some(device) = self.devices.get(&address) {
+            lock_device(device).write(data)?;
+        }
+        self.last_writes.insert(address, data.to_vec());
+        Ok(())
+    }
+
+    /// Fill `buffer` from an attached device, else the response keyed by the
+    /// preceding write, else the per-address response
+    fn respond(&self, address: u8, buffer: &mut [u8]) -> I2cResult<usize> {
+        if let Some(device) = self.devices.get(&address) {
+            lock_device(device).read(buffer)?;
+            return Ok(buffer.len());
         }
 
-        if let Some(response) = self.read_responses.get(&address) {
+        let keyed = self
+            .last_writes
+            .get(&address)
+            .and_then(|written| self.write_read_responses.get(&(address, written.clone())));
+
+        if let Some(response) = keyed.or_else(|| self.read_responses.get(&address)) {
             let bytes_to_copy = std::cmp::min(buffer.len(), response.len());
             buffer[..bytes_to_copy].copy_from_slice(&response[..bytes_to_copy]);
             Ok(bytes_to_copy)
@@ -188,41 +1098,712 @@ impl I2c for MockI2c {
     }
 }
 
+/// Lock a device, recovering it if a previous holder panicked mid-test
+fn lock_device<T: ?Sized>(device: &Mutex<T>) -> std::sync::MutexGuard<'_, T> {
+    device
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+impl Default for MockI2c {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Describe transaction steps for expectation mismatches, reads by length only
+fn describe_i2c_operations(operations: &[I2cOperation<'_>]) -> String {
+    let steps: Vec<_> = operations
+        .iter()
+        .map(|operation| match operation {
+            I2cOperation::Write(data) => format!("Write({:?})", data),
+            I2cOperation::Read(buffer) => format!("Read(len {})", buffer.len()),
+        })
+        .collect();
+    format!("[{}]", steps.join(", "))
+}
+
+impl MockI2c {
+    fn write_inner(&mut self, address: u8, data: &[u8]) -> I2cResult<()> {
+        self.check_address_error(address)?;
+        if let Some(expectations) = &mut self.expectations {
+            let actual = I2cExpectation::Write {
+                address,
+                data: data.to_vec(),
+            };
+            expectations
+                .next(format!("{:?}", actual), |expected| {
+                    (*expected == actual).then_some(())
+                })
+                .map_err(I2cError::Other)?;
+            self.write_log.push((address, data.to_vec()));
+            return Ok(());
+        }
+        self.check_present(address)?;
+
+        self.deliver(address, data)?;
+        self.write_log.push((address, data.to_vec()));
+        Ok(())
+    }
+
+    fn read_inner(&mut self, address: u8, buffer: &mut [u8]) -> I2cResult<usize> {
+        self.check_address_error(address)?;
+        if let Some(expectations) = &mut self.expectations {
+            let actual = format!("Read {{ address: {}, len: {} }}", address, buffer.len());
+            let reply = expectations
+                .next(actual, |expected| match expected {
+                    I2cExpectation::Read { address: a, reply }
+                        if *a == address && reply.len() == buffer.len() =>
+                    {
+                        Some(reply.clone())
+                    }
+                    _ => None,
+                })
+                .map_err(I2cError::Other)?;
+            buffer.copy_from_slice(&reply);
+            return Ok(buffer.len());
+        }
+        self.check_present(address)?;
+
+        self.respond(address, buffer)
+    }
+
+    fn transaction_inner(
+        &mut self,
+        address: u8,
+        operations: &mut [I2cOperation<'_>],
+    ) -> I2cResult<()> {
+        self.check_address_error(address)?;
+        if let Some(expectations) = &mut self.expectations {
+            let actual = format!(
+                "Transaction {{ address: {}, operations: {} }}",
+                address,
+                describe_i2c_operations(operations)
+            );
+            let records = expectations
+                .next(actual, |expected| match expected {
+                    I2cExpectation::Transaction {
+                        address: a,
+                        operations: steps,
+                    } if *a == address
+                        && steps.len() == operations.len()
+                        && steps.iter().zip(operations.iter()).all(|pair| match pair {
+                            (I2cOpRecord::Write(want), I2cOperation::Write(data)) => want == data,
+                            (I2cOpRecord::Read(reply), I2cOperation::Read(buffer)) => {
+                                reply.len() == buffer.len()
+                            }
+                            _ => false,
+                        }) =>
+                    {
+                        Some(steps.clone())
+                    }
+                    _ => None,
+                })
+                .map_err(I2cError::Other)?;
+            for (operation, record) in operations.iter_mut().zip(&records) {
+                if let (I2cOperation::Read(buffer), I2cOpRecord::Read(reply)) = (operation, record)
+                {
+                    buffer.copy_from_slice(reply);
+                }
+            }
+            self.transaction_log.push((address, records));
+            return Ok(());
+        }
+        self.check_present(address)?;
+
+        let mut records = Vec::with_capacity(operations.len());
+        for operation in operations.iter_mut() {
+            match operation {
+                I2cOperation::Write(data) => {
+                    self.deliver(address, data)?;
+                    records.push(I2cOpRecord::Write(data.to_vec()));
+                }
+                I2cOperation::Read(buffer) => {
+                    self.respond(address, buffer)?;
+                    records.push(I2cOpRecord::Read(buffer.to_vec()));
+                }
+            }
+        }
+
+        self.transaction_log.push((address, records));
+        Ok(())
+    }
+}
+
+impl I2c for MockI2c {
+    fn write(&mut self, address: u8, data: &[u8]) -> I2cResult<()> {
+        self.charge(1 + data.len());
+        let failure = self
+            .next_fault(false)
+            .and_then(|fault| fault.error(&format!("I2C address 0x{:02X}", address)));
+        let result = match failure {
+            Some(error) => Err(error),
+            None => self.write_inner(address, data),
+        };
+        if let Some(tap) = &self.trace {
+            let op = TraceOp::I2cWrite {
+                address,
+                data: data.to_vec(),
+            };
+            tap.record_i2c(op, &result);
+        }
+        result
+    }
+
+    fn read(&mut self, address: u8, buffer: &mut [u8]) -> I2cResult<usize> {
+        self.charge(1 + buffer.len());
+        let fault = self.next_fault(true);
+        let failure = fault
+            .as_ref()
+            .and_then(|fault| fault.error(&format!("I2C address 0x{:02X}", address)));
+        let result = match failure {
+            Some(error) => Err(error),
+            None => self.read_inner(address, buffer),
+        };
+        if let Ok(len) = result {
+            self.corrupt(&mut [&mut buffer[..len]], fault.as_ref());
+        }
+        if let Some(tap) = &self.trace {
+            let len = *result.as_ref().unwrap_or(&0);
+            let op = TraceOp::I2cRead {
+                address,
+                data: buffer[..len].to_vec(),
+            };
+            tap.record_i2c(op, &result);
+        }
+        result
+    }
+
+    fn transaction(&mut self, address: u8, operations: &mut [I2cOperation<'_>]) -> I2cResult<()> {
+        let bytes: usize = operations
+            .iter()
+            .map(|operation| match operation {
+                I2cOperation::Write(data) => 1 + data.len(),
+                I2cOperation::Read(buffer) => 1 + buffer.len(),
+            })
+            .sum();
+        self.charge(bytes);
+        let reads_data = operations
+            .iter()
+            .any(|operation| matches!(operation, I2cOperation::Read(_)));
+        let fault = self.next_fault(reads_data);
+        let failure = fault
+            .as_ref()
+            .and_then(|fault| fault.error(&format!("I2C address 0x{:02X}", address)));
+        let result = match failure {
+            Some(error) => Err(error),
+            None => self.transaction_inner(address, operations),
+        };
+        if result.is_ok() {
+            let mut buffers: Vec<&mut [u8]> = operations
+                .iter_mut()
+                .filter_map(|operation| match operation {
+                    I2cOperation::Read(buffer) => Some(&mut **buffer),
+                    I2cOperation::Write(_) => None,
+                })
+                .collect();
+            self.corrupt(&mut buffers, fault.as_ref());
+        }
+        if let Some(tap) = &self.trace {
+            let op = TraceOp::I2cTransaction {
+                address,
+                operations: trace::i2c_records(operations),
+            };
+            tap.record_i2c(op, &result);
+        }
+        result
+    }
+}
+
+/// SPI clock polarity and phase
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum SpiMode {
+    /// CPOL=0, CPHA=0
+    #[default]
+    Mode0,
+    /// CPOL=0, CPHA=1
+    Mode1,
+    /// CPOL=1, CPHA=0
+    Mode2,
+    /// CPOL=1, CPHA=1
+    Mode3,
+}
+
+impl SpiMode {
+    /// Mode number as used by datasheets and spidev: bit 1 is CPOL, bit 0 is CPHA
+    pub fn number(self) -> u8 {
+        match self {
+            SpiMode::Mode0 => 0,
+            SpiMode::Mode1 => 1,
+            SpiMode::Mode2 => 2,
+            SpiMode::Mode3 => 3,
+        }
+    }
+
+    pub fn from_number(number: u8) -> Option<Self> {
+        match number {
+            0 => Some(SpiMode::Mode0),
+            1 => Some(SpiMode::Mode1),
+            2 => Some(SpiMode::Mode2),
+            3 => Some(SpiMode::Mode3),
+            _ => None,
+        }
+    }
+
+    /// Clock idles high
+    pub fn cpol(self) -> bool {
+        self.number() & 0b10 != 0
+    }
+
+    /// Data is sampled on the second clock edge
+    pub fn cpha(self) -> bool {
+        self.number() & 0b01 != 0
+    }
+}
+
+/// Order in which the bits of each word are clocked out
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum BitOrder {
+    #[default]
+    MsbFirst,
+    LsbFirst,
+}
+
+/// Clock and framing settings for talking to one SPI device
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SpiConfig {
+    pub mode: SpiMode,
+    pub speed_hz: u32,
+    pub bit_order: BitOrder,
+    pub bits_per_word: u8,
+}
+
+impl SpiConfig {
+    /// MSB-first, 8-bit words in the given mode and clock
+    pub fn new(mode: SpiMode, speed_hz: u32) -> Self {
+        Self {
+            mode,
+            speed_hz,
+            bit_order: BitOrder::MsbFirst,
+            bits_per_word: 8,
+        }
+    }
+
+    pub fn with_bit_order(mut self, bit_order: BitOrder) -> Self {
+        self.bit_order = bit_order;
+        self
+    }
+
+    pub fn with_bits_per_word(mut self, bits_per_word: u8) -> Self {
+        self.bits_per_word = bits_per_word;
+        self
+    }
+}
+
+impl fmt::Display for SpiConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "mode {} at {} Hz, {:?}, {} bits per word",
+            self.mode.number(),
+            self.speed_hz,
+            self.bit_order,
+            self.bits_per_word
+        )
+    }
+}
+
+impl Default for SpiConfig {
+    fn default() -> Self {
+        Self::new(SpiMode::Mode0, 1_000_000)
+    }
+}
+
+/// Line that selects a device on a shared SPI bus
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ChipSelect {
+    /// Native chip-select line of the controller (CE0, CE1, ...)
+    Hardware(u8),
+    /// Active-low GPIO pin driven around each transfer
+    Gpio(u8),
+}
+
+impl fmt::Display for ChipSelect {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChipSelect::Hardware(line) => write!(f, "CE{}", line),
+            ChipSelect::Gpio(pin) => write!(f, "GPIO{}", pin),
+        }
+    }
+}
+
+/// One segment of an SPI transaction
+#[derive(Debug)]
+pub enum SpiOperation<'a> {
+    /// Clock bytes out, discarding what comes back
+    Write(&'a [u8]),
+    /// Clock zeros out, capturing what comes back
+    Read(&'a mut [u8]),
+    /// Full duplex: clock bytes out and replace them with what comes back
+    Transfer(&'a mut [u8]),
+}
+
+/// Recorded segment of an SPI transaction, with the bytes that were received
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SpiOpRecord {
+    Write(Vec<u8>),
+    Read(Vec<u8>),
+    Transfer { sent: Vec<u8>, received: Vec<u8> },
+}
+
 /// SPI abstraction trait for expandable hardware interfaces
 pub trait Spi {
     fn transfer(&mut self, data: &mut [u8]) -> Result<()>;
+    /// Clock segments back to back while chip select stays asserted
+    fn transaction(&mut self, operations: &mut [SpiOperation<'_>]) -> Result<()>;
+    /// Apply the settings of the device about to be addressed
+    fn configure(&mut self, config: &SpiConfig) -> Result<()>;
+    /// Route the following transfers to the device on `cs`
+    ///
+    /// For `ChipSelect::Gpio` the caller drives the pin; the controller only
+    /// has to keep its own chip-select lines idle.
+    fn select(&mut self, cs: ChipSelect) -> Result<()>;
+
+    /// Send bytes without capturing the reply
+    fn write(&mut self, data: &[u8]) -> Result<()> {
+        self.transaction(&mut [SpiOperation::Write(data)])
+    }
+
+    /// Clock in bytes while sending zeros
+    fn read(&mut self, buffer: &mut [u8]) -> Result<()> {
+        self.transaction(&mut [SpiOperation::Read(buffer)])
+    }
+
+    /// Send a command then read the reply under one chip-select assertion
+    fn write_read(&mut self, data: &[u8], buffer: &mut [u8]) -> Result<()> {
+        self.transaction(&mut [SpiOperation::Write(data), SpiOperation::Read(buffer)])
+    }
+}
+
+/// Simulated chip that computes `MockSpi` replies from what it is sent
+pub trait SpiResponder: Debug + Send {
+    /// Return the bytes clocked back during one chip-select assertion
+    ///
+    /// `sent` is everything clocked out, with zeros for read segments. The
+    /// reply lines up with it byte for byte and is padded with zeros if short.
+    fn respond(&mut self, sent: &[u8]) -> Vec<u8>;
 }
 
 /// Mock SPI implementation for testing
+///
+/// Replies come from a responder attached to the selected device if there is
+/// one, otherwise from the FIFO of scripted responses. Clones share responders.
 #[derive(Debug, Clone)]
 pub struct MockSpi {
     transfer_log: Vec<Vec<u8>>,
+    transaction_log: Vec<Vec<SpiOpRecord>>,
+    target_log: Vec<Option<ChipSelect>>,
+    config_log: Vec<SpiConfig>,
     responses: Vec<Vec<u8>>,
     response_index: usize,
+    responders: HashMap<ChipSelect, Arc<Mutex<dyn SpiResponder>>>,
+    default_responder: Option<Arc<Mutex<dyn SpiResponder>>>,
     should_fail: bool,
+    fault_plan: Option<FaultPlan>,
+    latency: Option<Latency>,
+    selected: Option<ChipSelect>,
+    config: SpiConfig,
+    required_config: Option<SpiConfig>,
+    expectations: Option<Expectations<SpiExpectation>>,
+    trace: Option<Tap>,
 }
 
 impl MockSpi {
     pub fn new() -> Self {
         Self {
             transfer_log: Vec::new(),
+            transaction_log: Vec::new(),
+            target_log: Vec::new(),
+            config_log: Vec::new(),
             responses: Vec::new(),
             response_index: 0,
+            responders: HashMap::new(),
+            default_responder: None,
             should_fail: false,
+            fault_plan: None,
+            latency: None,
+            selected: None,
+            config: SpiConfig::default(),
+            required_config: None,
+            expectations: None,
+            trace: None,
         }
     }
 
+    /// Append every chip-select assertion to a shared trace, under the name `source`
+    pub fn set_trace(&mut self, trace: &TraceRecorder, source: &str) {
+        self.trace = Some(Tap::new(trace, source));
+    }
+
+    /// Switch to strict mode: every chip-select assertion must match the next expectation
+    ///
+    /// Replies come from the expectations instead of responders or scripted
+    /// responses.
+    pub fn expect(&mut self, expectations: Vec<SpiExpectation>) {
+        self.expectations = Some(Expectations::new("SPI", expectations));
+    }
+
+    /// Check that every expectation was met and nothing unexpected happened
+    pub fn done(&self) -> Result<()> {
+        match &self.expectations {
+            Some(expectations) => expectations.done().map_err(anyhow::Error::msg),
+            None => Ok(()),
+        }
+    }
+
+    /// Queue a reply for the next segment that captures data (`Read` or `Transfer`)
     pub fn add_response(&mut self, response: Vec<u8>) {
         self.responses.push(response);
     }
 
+    /// Attach a simulated chip that answers all traffic to `cs`
+    ///
+    /// The returned handle lets tests inspect or poke the chip afterwards.
+    pub fn attach_responder<R: SpiResponder + 'static>(
+        &mut self,
+        cs: ChipSelect,
+        responder: R,
+    ) -> Arc<Mutex<R>> {
+        let responder = Arc::new(Mutex::new(responder));
+        self.responders.insert(cs, responder.clone());
+        responder
+    }
+
+    /// Answer traffic to devices without their own responder (including
+    /// transfers made without selecting a device)
+    pub fn set_responder<R: SpiResponder + 'static>(&mut self, responder: R) -> Arc<Mutex<R>> {
+        let responder = Arc::new(Mutex::new(responder));
+        self.default_responder = Some(responder.clone());
+        responder
+    }
+
     pub fn set_failure(&mut self, should_fail: bool) {
         self.should_fail = should_fail;
     }
 
+    /// Inject faults into transactions as `plan` schedules
+    ///
+    /// Fails if the plan uses `Fault::FailWith`, which only fits I2C.
+    pub fn set_fault_plan(&mut self, plan: FaultPlan) -> Result<()> {
+        plan.check_bus("SPI")?;
+        self.fault_plan = Some(plan);
+        Ok(())
+    }
+
+    pub fn get_fault_plan(&self) -> Option<&FaultPlan> {
+        self.fault_plan.as_ref()
+    }
+
+    /// Make every transaction take time on `clock`
+    ///
+    /// With `BusTiming::spi()` bytes are clocked at the configured `speed_hz`.
+    pub fn set_latency(&mut self, clock: &VirtualClock, timing: BusTiming) {
+        self.latency = Some(Latency::new(clock, timing));
+    }
+
+    /// Get the total simulated time spent on the bus
+    pub fn get_bus_time(&self) -> Duration {
+        self.latency.as_ref().map_or(Duration::ZERO, Latency::total)
+    }
+
+    /// Run a transaction, failing or corrupting it as the fault plan schedules
+    fn faulty_transaction(&mut self, operations: &mut [SpiOperation<'_>]) -> Result<()> {
+        let Some(plan) = &mut self.fault_plan else {
+            return self.transaction_inner(operations);
+        };
+        let reads_data = operations
+            .iter()
+            .any(|operation| !matches!(operation, SpiOperation::Write(_)));
+        let fault = plan.next(reads_data);
+        if let Some(error) = fault.as_ref().and_then(|fault| fault.error("SPI bus")) {
+            return Err(error.into());
+        }
+
+        self.transaction_inner(operations)?;
+        if let (Some(plan), Some(Fault::FlipBits(flips))) = (&mut self.fault_plan, fault) {
+            let mut buffers: Vec<&mut [u8]> = operations
+                .iter_mut()
+                .filter_map(|operation| match operation {
+                    SpiOperation::Read(buffer) | SpiOperation::Transfer(buffer) => {
+                        Some(&mut **buffer)
+                    }
+                    SpiOperation::Write(_) => None,
+                })
+                .collect();
+            plan.corrupt(&mut buffers, flips);
+        }
+        Ok(())
+    }
+
+    /// Get the bytes clocked out under each chip-select assertion (zeros for reads)
     pub fn get_transfer_log(&self) -> &[Vec<u8>] {
         &self.transfer_log
     }
+
+    /// Get the segments of each chip-select assertion, parallel to the transfer log
+    pub fn get_transaction_log(&self) -> &[Vec<SpiOpRecord>] {
+        &self.transaction_log
+    }
+
+    /// Get the device selected for each logged transfer (`None` if nothing was selected)
+    pub fn get_target_log(&self) -> &[Option<ChipSelect>] {
+        &self.target_log
+    }
+
+    /// Get the transfers that were sent to one device
+    pub fn get_transfers_to(&self, cs: ChipSelect) -> Vec<Vec<u8>> {
+        self.transfer_log
+            .iter()
+            .zip(&self.target_log)
+            .filter(|(_, target)| **target == Some(cs))
+            .map(|(data, _)| data.clone())
+            .collect()
+    }
+
+    /// Get the currently selected device
+    pub fn get_selected(&self) -> Option<ChipSelect> {
+        self.selected
+    }
+
+    /// Get the settings last applied with `configure`
+    pub fn get_config(&self) -> SpiConfig {
+        self.config
+    }
+
+    /// Get the settings in effect for each logged transfer
+    pub fn get_config_log(&self) -> &[SpiConfig] {
+        &self.config_log
+    }
+
+    /// Make transfers fail unless the bus is set up the way the simulated chip needs
+    ///
+    /// Mode, bit order and word size must match exactly; the clock may be at
+    /// most `config.speed_hz`.
+    pub fn require_config(&mut self, config: SpiConfig) {
+        self.required_config = Some(config);
+    }
+
+    fn check_config(&self) -> Result<()> {
+        let Some(required) = self.required_config else {
+            return Ok(());
+        };
+        let actual = self.config;
+        if actual.mode != required.mode
+            || actual.bit_order != required.bit_order
+            || actual.bits_per_word != required.bits_per_word
+            || actual.speed_hz > required.speed_hz
+        {
+            return Err(anyhow::anyhow!(
+                "Simulated SPI device needs {} but the bus is set to {}",
+                required,
+                actual
+            ));
+        }
+        Ok(())
+    }
+
+    /// Ask the selected device's responder for the reply to a whole transaction
+    fn responder_reply(&self, sent: &[u8]) -> Option<Vec<u8>> {
+        let responder = self
+            .selected
+            .and_then(|cs| self.responders.get(&cs))
+            .or(self.default_responder.as_ref())?;
+        let mut reply = lock_device(responder).respond(sent);
+        reply.resize(sent.len(), 0);
+        Some(reply)
+    }
+
+    /// Fill a capturing segment from the next scripted response, if any
+    fn respond(&mut self, buffer: &mut [u8]) {
+        if self.response_index < self.responses.len() {
+            let response = &self.responses[self.response_index];
+            let bytes_to_copy = std::cmp::min(buffer.len(), response.len());
+            buffer[..bytes_to_copy].copy_from_slice(&response[..bytes_to_copy]);
+            self.response_index += 1;
+        }
+    }
+
+    fn transaction_inner(&mut self, operations: &mut [SpiOperation<'_>]) -> Result<()> {
+        if self.should_fail {
+            return Err(anyhow::anyhow!("Simulated SPI failure"));
+        }
+        self.check_config()?;
+
+        let sent: Vec<u8> = operations
+            .iter()
+            .flat_map(|operation| match operation {
+                SpiOperation::Write(data) => data.to_vec(),
+                SpiOperation::Read(buffer) => vec![0; buffer.len()],
+                SpiOperation::Transfer(data) => data.to_vec(),
+            })
+            .collect();
+        let reply = match &mut self.expectations {
+            Some(expectations) => {
+                let actual = format!(
+                    "SpiExpectation {{ target: {:?}, operations: {} }}",
+                    self.selected,
+                    describe_spi_operations(operations)
+                );
+                let reply = expectations
+                    .next(actual, |expected| {
+                        expected_spi_reply(expected, self.selected, operations)
+                    })
+                    .map_err(anyhow::Error::msg)?;
+                Some(reply)
+            }
+            None => self.responder_reply(&sent),
+        };
+
+        let mut offset = 0;
+        let mut records = Vec::with_capacity(operations.len());
+        for operation in operations.iter_mut() {
+            match operation {
+                SpiOperation::Write(data) => {
+                    offset += data.len();
+                    records.push(SpiOpRecord::Write(data.to_vec()));
+                }
+                SpiOperation::Read(buffer) => {
+                    match &reply {
+                        Some(reply) => {
+                            buffer.copy_from_slice(&reply[offset..offset + buffer.len()])
+                        }
+                        None => self.respond(buffer),
+                    }
+                    offset += buffer.len();
+                    records.push(SpiOpRecord::Read(buffer.to_vec()));
+                }
+                SpiOperation::Transfer(data) => {
+                    let out = data.to_vec();
+                    match &reply {
+                        Some(reply) => data.copy_from_slice(&reply[offset..offset + data.len()]),
+                        None => self.respond(data),
+                    }
+                    offset += data.len();
+                    records.push(SpiOpRecord::Transfer {
+                        sent: out,
+                        received: data.to_vec(),
+                    });
+                }
+            }
+        }
+
+        // Log the outgoing data and what the bus looked like at the time
+        self.transfer_log.push(sent);
+        self.transaction_log.push(records);
+        self.target_log.push(self.selected);
+        self.config_log.push(self.config);
+        Ok(())
+    }
 }
 
 impl Default for MockSpi {
@@ -231,23 +1812,89 @@ impl Default for MockSpi {
     }
 }
 
-impl Spi for MockSpi {
-    fn transfer(&mut self, data: &mut [u8]) -> Result<()> {
-        if self.should_fail {
-            return Err(anyhow::anyhow!("Simulated SPI failure"));
+/// Describe transaction segments for expectation mismatches, reads by length only
+fn describe_spi_operations(operations: &[SpiOperation<'_>]) -> String {
+    let steps: Vec<_> = operations
+        .iter()
+        .map(|operation| match operation {
+            SpiOperation::Write(data) => format!("Write({:?})", data),
+            SpiOperation::Read(buffer) => format!("Read(len {})", buffer.len()),
+            SpiOperation::Transfer(data) => format!("Transfer {{ sent: {:?} }}", data),
+        })
+        .collect();
+    format!("[{}]", steps.join(", "))
+}
+
+/// The reply stream for a transaction if it matches an expectation
+fn expected_spi_reply(
+    expected: &SpiExpectation,
+    selected: Option<ChipSelect>,
+    operations: &[SpiOperation<'_>],
+) -> Option<Vec<u8>> {
+    if expected.target.is_some() && expected.target != selected {
+        return None;
+    }
+    if expected.operations.len() != operations.len() {
+        return None;
+    }
+
+    let mut reply = Vec::new();
+    for pair in expected.operations.iter().zip(operations) {
+        match pair {
+            (SpiOpRecord::Write(want), SpiOperation::Write(data)) if want == data => {
+                reply.resize(reply.len() + data.len(), 0);
+            }
+            (SpiOpRecord::Read(data), SpiOperation::Read(buffer)) if data.len() == buffer.len() => {
+                reply.extend_from_slice(data);
+            }
+            (SpiOpRecord::Transfer { sent, received }, SpiOperation::Transfer(data))
+                if sent == data && received.len() == data.len() =>
+            {
+                reply.extend_from_slice(received);
+            }
+            _ => return None,
         }
+    }
+    Some(reply)
+}
 
-        // Log the input data
-        self.transfer_log.push(data.to_vec());
+impl Spi for MockSpi {
+    fn transfer(&mut self, data: &mut [u8]) -> Result<()> {
+        self.transaction(&mut [SpiOperation::Transfer(data)])
+    }
 
-        // If we have a scripted response, use it
-        if self.response_index < self.responses.len() {
-            let response = &self.responses[self.response_index];
-            let bytes_to_copy = std::cmp::min(data.len(), response.len());
-            data[..bytes_to_copy].copy_from_slice(&response[..bytes_to_copy]);
-            self.response_index += 1;
+    fn transaction(&mut self, operations: &mut [SpiOperation<'_>]) -> Result<()> {
+        if let Some(latency) = &mut self.latency {
+            let bytes = operations
+                .iter()
+                .map(|operation| match operation {
+                    SpiOperation::Write(data) => data.len(),
+                    SpiOperation::Read(buffer) | SpiOperation::Transfer(buffer) => buffer.len(),
+                })
+                .sum();
+            latency.charge(bytes, Some(self.config.speed_hz));
         }
+        let Some(tap) = self.trace.clone() else {
+            return self.faulty_transaction(operations);
+        };
+        let mut records = trace::spi_request(operations);
+        let result = self.faulty_transaction(operations);
+        trace::spi_complete(&mut records, operations);
+        let op = TraceOp::SpiTransaction {
+            target: self.selected,
+            operations: records,
+        };
+        tap.record(op, &result);
+        result
+    }
+
+    fn configure(&mut self, config: &SpiConfig) -> Result<()> {
+        self.config = *config;
+        Ok(())
+    }
 
+    fn select(&mut self, cs: ChipSelect) -> Result<()> {
+        self.selected = Some(cs);
         Ok(())
     }
 }
@@ -287,9 +1934,51 @@ mod tests {
         assert!(gpio.read(3).is_err());
     }
 
+    #[test]
+    fn test_mock_gpio_pin_modes() {
+        let mut gpio = MockGpio::new();
+        gpio.set_mode(4, PinMode::Input).unwrap();
+        gpio.set_mode(5, PinMode::Output).unwrap();
+
+        assert!(gpio.write(4, true).is_err());
+        assert!(gpio.write(5, true).is_ok());
+        assert_eq!(gpio.get_pin_mode(4), Some(PinMode::Input));
+        assert_eq!(gpio.get_pin_mode(6), None);
+    }
+
+    #[test]
+    fn test_mock_gpio_pull_resolution() {
+        let mut gpio = MockGpio::new();
+        gpio.set_mode(7, PinMode::Input).unwrap();
+
+        assert!(!gpio.read(7).unwrap());
+        assert_eq!(gpio.get_floating_read_count(7), 1);
+
+        gpio.set_pull(7, Pull::Up).unwrap();
+        assert!(gpio.read(7).unwrap());
+        assert_eq!(gpio.get_floating_read_count(7), 1);
+    }
+
+    #[test]
+    fn test_mock_gpio_edge_events_from_stimulus() {
+        let mut gpio = MockGpio::new();
+        gpio.set_mode(8, PinMode::Input).unwrap();
+        gpio.subscribe(8, Edge::Rising).unwrap();
+
+        gpio.set_input_level(8, true);
+        gpio.set_input_level(8, false);
+        gpio.set_input_level(8, true);
+
+        let events: Vec<_> = gpio.events().map(|e| e.unwrap()).collect();
+        assert_eq!(events.len(), 2);
+        assert!(events.iter().all(|e| e.pin == 8 && e.edge == Edge::Rising));
+        assert!(events[0].timestamp <= events[1].timestamp);
+    }
+
     #[test]
     fn test_mock_i2c_operations() {
         let mut i2c = MockI2c::new();
+        i2c.add_address(0x48);
 
         // Test write
         i2c.write(0x48, &[0x01, 0x02]).unwrap();
@@ -304,6 +1993,43 @@ mod tests {
         assert_eq!(&buffer[..2], &[0xAB, 0xCD]);
     }
 
+    #[test]
+    fn test_mock_i2c_write_read() {
+        let mut i2c = MockI2c::new();
+        i2c.set_write_read_response(0x76, &[0xD0], vec![0x60]);
+
+        let mut id = [0u8; 1];
+        i2c.write_read(0x76, &[0xD0], &mut id).unwrap();
+
+        assert_eq!(id, [0x60]);
+        assert!(i2c.get_write_log().is_empty());
+        assert_eq!(
+            i2c.get_transaction_log()[0],
+            (
+                0x76,
+                vec![
+                    I2cOpRecord::Write(vec![0xD0]),
+                    I2cOpRecord::Read(vec![0x60])
+                ]
+            )
+        );
+    }
+
+    #[test]
+    fn test_mock_i2c_nacks_unknown_addresses() {
+        let mut i2c = MockI2c::new();
+        let mut buffer = [0u8; 1];
+
+        assert_eq!(i2c.write(0x3C, &[0x00]), Err(I2cError::AddressNack(0x3C)));
+        assert_eq!(
+            i2c.read(0x3C, &mut buffer),
+            Err(I2cError::AddressNack(0x3C))
+        );
+
+        i2c.set_address_error(0x3D, I2cError::ArbitrationLoss);
+        assert_eq!(i2c.write(0x3D, &[0x00]), Err(I2cError::ArbitrationLoss));
+    }
+
     #[test]
     fn test_mock_spi_transfer() {
         let mut spi = MockSpi::new();
@@ -316,4 +2042,89 @@ mod tests {
         assert_eq!(spi.get_transfer_log().len(), 1);
         assert_eq!(spi.get_transfer_log()[0], vec![0x01, 0x02]);
     }
+
+    #[test]
+    fn test_mock_spi_rejects_wrong_config() {
+        let mut spi = MockSpi::new();
+        let chip = SpiConfig::new(SpiMode::Mode3, 10_000_000).with_bit_order(BitOrder::LsbFirst);
+        spi.require_config(chip);
+
+        // Default is mode 0, MSB first
+        assert!(spi.transfer(&mut [0x80]).is_err());
+        assert!(spi.get_transfer_log().is_empty());
+
+        // A slower clock than the chip's maximum is fine
+        let slower = SpiConfig {
+            speed_hz: 1_000_000,
+            ..chip
+        };
+        spi.configure(&slower).unwrap();
+        spi.transfer(&mut [0x80]).unwrap();
+        assert_eq!(spi.get_config_log(), &[slower]);
+
+        spi.configure(&SpiConfig {
+            speed_hz: 20_000_000,
+            ..chip
+        })
+        .unwrap();
+        assert!(spi.transfer(&mut [0x80]).is_err());
+        assert!(SpiMode::Mode3.cpol() && SpiMode::Mode3.cpha());
+        assert_eq!(SpiMode::from_number(2), Some(SpiMode::Mode2));
+    }
+
+    #[test]
+    fn test_mock_spi_transaction_segments() {
+        let mut spi = MockSpi::new();
+        spi.add_response(vec![0xEF, 0x40, 0x18]);
+        spi.add_response(vec![0x55]);
+
+        let mut id = [0u8; 3];
+        spi.write_read(&[0x9F], &mut id).unwrap();
+        assert_eq!(id, [0xEF, 0x40, 0x18]);
+
+        let mut status = [0x05, 0x00];
+        spi.transaction(&mut [
+            SpiOperation::Write(&[0x06]),
+            SpiOperation::Transfer(&mut status),
+        ])
+        .unwrap();
+        assert_eq!(status, [0x55, 0x00]);
+
+        assert_eq!(
+            spi.get_transfer_log(),
+            &[vec![0x9F, 0x00, 0x00, 0x00], vec![0x06, 0x05, 0x00]]
+        );
+        assert_eq!(
+            spi.get_transaction_log()[1],
+            vec![
+                SpiOpRecord::Write(vec![0x06]),
+                SpiOpRecord::Transfer {
+                    sent: vec![0x05, 0x00],
+                    received: vec![0x55, 0x00],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_mock_gpio_strict_expectations() {
+        let mut gpio = MockGpio::new();
+        gpio.expect(vec![
+            GpioExpectation::SetMode(17, PinMode::Input),
+            GpioExpectation::Read(17, true),
+            GpioExpectation::Write(18, true),
+        ]);
+
+        gpio.set_mode(17, PinMode::Input).unwrap();
+        assert!(gpio.read(17).unwrap());
+        assert!(gpio.done().is_err());
+
+        let err = gpio.write(18, false).unwrap_err();
+        assert!(err.to_string().contains("expected: Write(18, true)"));
+        assert!(err.to_string().contains("actual: Write(18, false)"));
+
+        // The mismatch sticks even after the expected call arrives
+        gpio.write(18, true).unwrap();
+        assert!(gpio.done().is_err());
+    }
 }
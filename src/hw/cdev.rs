@@ -0,0 +1,644 @@
+//! Linux GPIO character device backend (`/dev/gpiochipN`, uAPI v2)
+
+use super::{Edge, EdgeEvent, Gpio, GpioEvents, PinMode, Pull};
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read};
+use std::mem::size_of;
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+const GPIO_MAX_NAME_SIZE: usize = 32;
+const GPIO_V2_LINES_MAX: usize = 64;
+const GPIO_V2_LINE_NUM_ATTRS_MAX: usize = 10;
+
+const GPIO_V2_LINE_FLAG_INPUT: u64 = 1 << 2;
+const GPIO_V2_LINE_FLAG_OUTPUT: u64 = 1 << 3;
+const GPIO_V2_LINE_FLAG_EDGE_RISING: u64 = 1 << 4;
+const GPIO_V2_LINE_FLAG_EDGE_FALLING: u64 = 1 << 5;
+const GPIO_V2_LINE_FLAG_BIAS_PULL_UP: u64 = 1 << 8;
+const GPIO_V2_LINE_FLAG_BIAS_PULL_DOWN: u64 = 1 << 9;
+const GPIO_V2_LINE_FLAG_BIAS_DISABLED: u64 = 1 << 10;
+
+const GPIO_V2_LINE_ATTR_ID_OUTPUT_VALUES: u32 = 2;
+
+const GPIO_V2_LINE_EVENT_RISING_EDGE: u32 = 1;
+
+const fn ioc(dir: u64, nr: u64, size: usize) -> u64 {
+    (dir << 30) | ((size as u64) << 16) | (0xB4 << 8) | nr
+}
+
+const IOC_READ: u64 = 2;
+const IOC_READ_WRITE: u64 = 3;
+
+pub(crate) const GPIO_GET_CHIPINFO_IOCTL: u64 = ioc(IOC_READ, 0x01, size_of::<ChipInfo>());
+pub(crate) const GPIO_V2_GET_LINE_IOCTL: u64 = ioc(IOC_READ_WRITE, 0x07, size_of::<LineRequest>());
+pub(crate) const GPIO_V2_LINE_SET_CONFIG_IOCTL: u64 =
+    ioc(IOC_READ_WRITE, 0x0D, size_of::<LineConfig>());
+pub(crate) const GPIO_V2_LINE_GET_VALUES_IOCTL: u64 =
+    ioc(IOC_READ_WRITE, 0x0E, size_of::<LineValues>());
+pub(crate) const GPIO_V2_LINE_SET_VALUES_IOCTL: u64 =
+    ioc(IOC_READ_WRITE, 0x0F, size_of::<LineValues>());
+
+/// `struct gpiochip_info`
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct ChipInfo {
+    pub name: [u8; GPIO_MAX_NAME_SIZE],
+    pub label: [u8; GPIO_MAX_NAME_SIZE],
+    pub lines: u32,
+}
+
+/// `struct gpio_v2_line_values`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct LineValues {
+    pub bits: u64,
+    pub mask: u64,
+}
+
+/// `struct gpio_v2_line_attribute`, with the union flattened to its 64-bit form
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct LineAttribute {
+    pub id: u32,
+    pub padding: u32,
+    pub value: u64,
+}
+
+/// `struct gpio_v2_line_config_attribute`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct LineConfigAttribute {
+    pub attr: LineAttribute,
+    pub mask: u64,
+}
+
+/// `struct gpio_v2_line_config`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct LineConfig {
+    pub flags: u64,
+    pub num_attrs: u32,
+    pub padding: [u32; 5],
+    pub attrs: [LineConfigAttribute; GPIO_V2_LINE_NUM_ATTRS_MAX],
+}
+
+/// `struct gpio_v2_line_request`
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct LineRequest {
+    pub offsets: [u32; GPIO_V2_LINES_MAX],
+    pub consumer: [u8; GPIO_MAX_NAME_SIZE],
+    pub config: LineConfig,
+    pub num_lines: u32,
+    pub event_buffer_size: u32,
+    pub padding: [u32; 5],
+    pub fd: i32,
+}
+
+/// `struct gpio_v2_line_event`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct LineEvent {
+    pub timestamp_ns: u64,
+    pub id: u32,
+    pub offset: u32,
+    pub seqno: u32,
+    pub line_seqno: u32,
+    pub padding: [u32; 6],
+}
+
+const _: () = assert!(size_of::<ChipInfo>() == 68);
+const _: () = assert!(size_of::<LineConfig>() == 272);
+const _: () = assert!(size_of::<LineRequest>() == 592);
+const _: () = assert!(size_of::<LineEvent>() == 48);
+
+/// The GPIO ioctls, separated from the `Gpio` logic so tests can substitute a fake chip
+pub(crate) trait ChipIo {
+    fn chip_info(&mut self, chip: &File) -> io::Result<ChipInfo>;
+    /// Request lines from the chip, returning the line request file
+    fn get_line(&mut self, chip: &File, request: &mut LineRequest) -> io::Result<File>;
+    fn set_config(&mut self, line: &File, config: &mut LineConfig) -> io::Result<()>;
+    fn get_values(&mut self, line: &File, values: &mut LineValues) -> io::Result<()>;
+    fn set_values(&mut self, line: &File, values: &mut LineValues) -> io::Result<()>;
+}
+
+/// `ChipIo` backed by real ioctls on the character device
+struct SysChipIo;
+
+fn ioctl<T>(file: &File, request: u64, arg: &mut T) -> io::Result<()> {
+    // SAFETY: `arg` is a live, exclusively borrowed `#[repr(C)]` struct whose
+    // size is encoded in `request`, so the kernel stays within its bounds.
+    let ret = unsafe { libc::ioctl(file.as_raw_fd(), request as _, arg as *mut T) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// `poll` timeout in whole milliseconds, rounded up so short waits still wait
+fn poll_timeout_ms(timeout: Duration) -> i32 {
+    timeout.as_nanos().div_ceil(1_000_000).min(i32::MAX as u128) as i32
+}
+
+impl ChipIo for SysChipIo {
+    fn chip_info(&mut self, chip: &File) -> io::Result<ChipInfo> {
+        let mut info = ChipInfo {
+            name: [0; GPIO_MAX_NAME_SIZE],
+            label: [0; GPIO_MAX_NAME_SIZE],
+            lines: 0,
+        };
+        ioctl(chip, GPIO_GET_CHIPINFO_IOCTL, &mut info)?;
+        Ok(info)
+    }
+
+    fn get_line(&mut self, chip: &File, request: &mut LineRequest) -> io::Result<File> {
+        ioctl(chip, GPIO_V2_GET_LINE_IOCTL, request)?;
+        // SAFETY: on success the kernel hands us a new fd that nothing else owns
+        Ok(unsafe { File::from_raw_fd(request.fd) })
+    }
+
+    fn set_config(&mut self, line: &File, config: &mut LineConfig) -> io::Result<()> {
+        ioctl(line, GPIO_V2_LINE_SET_CONFIG_IOCTL, config)
+    }
+
+    fn get_values(&mut self, line: &File, values: &mut LineValues) -> io::Result<()> {
+        ioctl(line, GPIO_V2_LINE_GET_VALUES_IOCTL, values)
+    }
+
+    fn set_values(&mut self, line: &File, values: &mut LineValues) -> io::Result<()> {
+        ioctl(line, GPIO_V2_LINE_SET_VALUES_IOCTL, values)
+    }
+}
+
+struct Line {
+    file: File,
+    mode: PinMode,
+    pull: Option<Pull>,
+    edge: Option<Edge>,
+}
+
+/// `Gpio` implementation on a Linux GPIO character device
+///
+/// Each pin is requested as its own single-line request the first time it is
+/// configured or used; pins are line offsets on the chip.
+pub struct CdevGpio {
+    chip: File,
+    io: Box<dyn ChipIo>,
+    consumer: String,
+    num_lines: u32,
+    lines: HashMap<u8, Line>,
+}
+
+impl CdevGpio {
+    /// Open a chip such as `/dev/gpiochip0`
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let chip = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .with_context(|| format!("Failed to open GPIO chip {}", path.display()))?;
+        Self::with_io(chip, Box::new(SysChipIo))
+    }
+
+    pub(crate) fn with_io(chip: File, mut io: Box<dyn ChipIo>) -> Result<Self> {
+        let info = io
+            .chip_info(&chip)
+            .context("Failed to query GPIO chip info")?;
+        Ok(Self {
+            chip,
+            io,
+            consumer: env!("CARGO_PKG_NAME").to_string(),
+            num_lines: info.lines,
+            lines: HashMap::new(),
+        })
+    }
+
+    /// Set the consumer label reported for requested lines (e.g. by `gpioinfo`)
+    pub fn with_consumer(mut self, consumer: &str) -> Self {
+        self.consumer = consumer.to_string();
+        self
+    }
+
+    /// Get the number of lines the chip exposes
+    pub fn num_lines(&self) -> u32 {
+        self.num_lines
+    }
+
+    fn line_flags(mode: PinMode, pull: Option<Pull>, edge: Option<Edge>) -> Result<u64> {
+        let mut flags = match mode {
+            PinMode::Input => GPIO_V2_LINE_FLAG_INPUT,
+            PinMode::Output => GPIO_V2_LINE_FLAG_OUTPUT,
+            PinMode::Alt(func) => {
+                return Err(anyhow::anyhow!(
+                    "ALT{} is not supported by the GPIO character device",
+                    func
+                ));
+            }
+        };
+
+        flags |= match pull {
+            Some(Pull::Up) => GPIO_V2_LINE_FLAG_BIAS_PULL_UP,
+            Some(Pull::Down) => GPIO_V2_LINE_FLAG_BIAS_PULL_DOWN,
+            Some(Pull::None) => GPIO_V2_LINE_FLAG_BIAS_DISABLED,
+            None => 0,
+        };
+
+        flags |= match edge {
+            Some(Edge::Rising) => GPIO_V2_LINE_FLAG_EDGE_RISING,
+            Some(Edge::Falling) => GPIO_V2_LINE_FLAG_EDGE_FALLING,
+            Some(Edge::Both) => GPIO_V2_LINE_FLAG_EDGE_RISING | GPIO_V2_LINE_FLAG_EDGE_FALLING,
+            None => 0,
+        };
+
+        Ok(flags)
+    }
+
+    /// Request or reconfigure the line behind `pin`
+    fn configure(
+        &mut self,
+        pin: u8,
+        mode: PinMode,
+        pull: Option<Pull>,
+        edge: Option<Edge>,
+        initial: Option<bool>,
+    ) -> Result<()> {
+        if u32::from(pin) >= self.num_lines {
+            return Err(anyhow::anyhow!(
+                "GPIO line {} out of range (chip has {} lines)",
+                pin,
+                self.num_lines
+            ));
+        }
+
+        let mut config = LineConfig {
+            flags: Self::line_flags(mode, pull, edge)?,
+            ..Default::default()
+        };
+        if let Some(high) = initial {
+            config.num_attrs = 1;
+            config.attrs[0] = LineConfigAttribute {
+                attr: LineAttribute {
+                    id: GPIO_V2_LINE_ATTR_ID_OUTPUT_VALUES,
+                    padding: 0,
+                    value: u64::from(high),
+                },
+                mask: 1,
+            };
+        }
+
+        if let Some(line) = self.lines.get_mut(&pin) {
+            self.io
+                .set_config(&line.file, &mut config)
+                .with_context(|| format!("Failed to reconfigure GPIO line {}", pin))?;
+            line.mode = mode;
+            line.pull = pull;
+            line.edge = edge;
+            return Ok(());
+        }
+
+        let mut request = LineRequest {
+            offsets: [0; GPIO_V2_LINES_MAX],
+            consumer: [0; GPIO_MAX_NAME_SIZE],
+            config,
+            num_lines: 1,
+            event_buffer_size: 0,
+            padding: [0; 5],
+            fd: -1,
+        };
+        request.offsets[0] = u32::from(pin);
+        let label = self.consumer.as_bytes();
+        let len = label.len().min(GPIO_MAX_NAME_SIZE - 1);
+        request.consumer[..len].copy_from_slice(&label[..len]);
+
+        let file = self
+            .io
+            .get_line(&self.chip, &mut request)
+            .with_context(|| format!("Failed to request GPIO line {}", pin))?;
+        self.lines.insert(
+            pin,
+            Line {
+                file,
+                mode,
+                pull,
+                edge,
+            },
+        );
+        Ok(())
+    }
+}
+
+impl Gpio for CdevGpio {
+    fn write(&mut self, pin: u8, high: bool) -> Result<()> {
+        let Some(line) = self.lines.get(&pin) else {
+            return self.configure(pin, PinMode::Output, None, None, Some(high));
+        };
+        if line.mode != PinMode::Output {
+            return Err(anyhow::anyhow!("Cannot write to input pin {}", pin));
+        }
+
+        let mut values = LineValues {
+            bits: u64::from(high),
+            mask: 1,
+        };
+        self.io
+            .set_values(&line.file, &mut values)
+            .with_context(|| format!("Failed to set GPIO line {}", pin))
+    }
+
+    fn read(&mut self, pin: u8) -> Result<bool> {
+        if !self.lines.contains_key(&pin) {
+            self.configure(pin, PinMode::Input, None, None, None)?;
+        }
+
+        let line = &self.lines[&pin];
+        let mut values = LineValues { bits: 0, mask: 1 };
+        self.io
+            .get_values(&line.file, &mut values)
+            .with_context(|| format!("Failed to read GPIO line {}", pin))?;
+        Ok(values.bits & 1 == 1)
+    }
+
+    fn set_mode(&mut self, pin: u8, mode: PinMode) -> Result<()> {
+        let (pull, edge) = match self.lines.get(&pin) {
+            // Edge detection is only available on inputs
+            Some(line) if mode == PinMode::Input => (line.pull, line.edge),
+            Some(line) => (line.pull, None),
+            None => (None, None),
+        };
+        self.configure(pin, mode, pull, edge, None)
+    }
+
+    fn set_pull(&mut self, pin: u8, pull: Pull) -> Result<()> {
+        let (mode, edge) = self
+            .lines
+            .get(&pin)
+            .map_or((PinMode::Input, None), |line| (line.mode, line.edge));
+        self.configure(pin, mode, Some(pull), edge, None)
+    }
+}
+
+impl GpioEvents for CdevGpio {
+    fn subscribe(&mut self, pin: u8, edge: Edge) -> Result<()> {
+        let pull = match self.lines.get(&pin) {
+            Some(line) if line.mode == PinMode::Output => {
+                return Err(anyhow::anyhow!(
+                    "Cannot subscribe to edges on output pin {}",
+                    pin
+                ));
+            }
+            Some(line) => line.pull,
+            None => None,
+        };
+        self.configure(pin, PinMode::Input, pull, Some(edge), None)
+    }
+
+    fn unsubscribe(&mut self, pin: u8) -> Result<()> {
+        match self.lines.get(&pin) {
+            Some(line) if line.edge.is_some() => {
+                let (mode, pull) = (line.mode, line.pull);
+                self.configure(pin, mode, pull, None, None)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns `None` immediately when no pin is subscribed.
+    fn wait_for_edge(&mut self, timeout: Duration) -> Result<Option<EdgeEvent>> {
+        let subscribed: Vec<u8> = self
+            .lines
+            .iter()
+            .filter(|(_, line)| line.edge.is_some())
+            .map(|(pin, _)| *pin)
+            .collect();
+        if subscribed.is_empty() {
+            return Ok(None);
+        }
+
+        let mut fds: Vec<libc::pollfd> = subscribed
+            .iter()
+            .map(|pin| libc::pollfd {
+                fd: self.lines[pin].file.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            })
+            .collect();
+        // A signal cuts the poll short; wait again for whatever time is left
+        let deadline = Instant::now().checked_add(timeout);
+        loop {
+            let remaining = match deadline {
+                Some(deadline) => deadline.saturating_duration_since(Instant::now()),
+                None => timeout,
+            };
+            // SAFETY: `fds` is a valid array of `fds.len()` pollfd structs
+            let ready = unsafe {
+                libc::poll(
+                    fds.as_mut_ptr(),
+                    fds.len() as libc::nfds_t,
+                    poll_timeout_ms(remaining),
+                )
+            };
+            if ready >= 0 {
+                break;
+            }
+            let err = io::Error::last_os_error();
+            if err.kind() != io::ErrorKind::Interrupted {
+                return Err(err).context("Failed to poll GPIO lines");
+            }
+        }
+
+        let Some(index) = fds.iter().position(|fd| fd.revents & libc::POLLIN != 0) else {
+            return Ok(None);
+        };
+        let pin = subscribed[index];
+
+        let mut buf = [0u8; size_of::<LineEvent>()];
+        // `read_exact` retries reads interrupted by a signal
+        (&self.lines[&pin].file)
+            .read_exact(&mut buf)
+            .with_context(|| format!("Failed to read edge event on GPIO line {}", pin))?;
+        // SAFETY: `LineEvent` is plain old data and `buf` holds exactly one
+        let event: LineEvent = unsafe { std::ptr::read_unaligned(buf.as_ptr().cast()) };
+
+        Ok(Some(EdgeEvent {
+            pin,
+            edge: if event.id == GPIO_V2_LINE_EVENT_RISING_EDGE {
+                Edge::Rising
+            } else {
+                Edge::Falling
+            },
+            timestamp: Duration::from_nanos(event.timestamp_ns),
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::prelude::*;
+    use std::cell::RefCell;
+    use std::io::Write;
+    use std::os::fd::{OwnedFd, RawFd};
+    use std::os::unix::net::UnixStream;
+    use std::rc::Rc;
+
+    /// In-memory chip: line requests are socket pairs so edge events can be
+    /// injected from the test side and polled for real.
+    #[derive(Default)]
+    struct FakeChipState {
+        lines: HashMap<RawFd, u32>,
+        flags: HashMap<u32, u64>,
+        values: HashMap<u32, bool>,
+        event_writers: HashMap<u32, UnixStream>,
+    }
+
+    struct FakeChip(Rc<RefCell<FakeChipState>>);
+
+    impl ChipIo for FakeChip {
+        fn chip_info(&mut self, _chip: &File) -> io::Result<ChipInfo> {
+            Ok(ChipInfo {
+                name: [0; GPIO_MAX_NAME_SIZE],
+                label: [0; GPIO_MAX_NAME_SIZE],
+                lines: 28,
+            })
+        }
+
+        fn get_line(&mut self, _chip: &File, request: &mut LineRequest) -> io::Result<File> {
+            let (ours, theirs) = UnixStream::pair()?;
+            let offset = request.offsets[0];
+            let mut state = self.0.borrow_mut();
+            state.lines.insert(ours.as_raw_fd(), offset);
+            state.event_writers.insert(offset, theirs);
+            drop(state);
+            self.set_flags(offset, &request.config);
+            Ok(File::from(OwnedFd::from(ours)))
+        }
+
+        fn set_config(&mut self, line: &File, config: &mut LineConfig) -> io::Result<()> {
+            let offset = self.0.borrow().lines[&line.as_raw_fd()];
+            self.set_flags(offset, config);
+            Ok(())
+        }
+
+        fn get_values(&mut self, line: &File, values: &mut LineValues) -> io::Result<()> {
+            let state = self.0.borrow();
+            let offset = state.lines[&line.as_raw_fd()];
+            values.bits = u64::from(state.values.get(&offset).copied().unwrap_or(false));
+            Ok(())
+        }
+
+        fn set_values(&mut self, line: &File, values: &mut LineValues) -> io::Result<()> {
+            let mut state = self.0.borrow_mut();
+            let offset = state.lines[&line.as_raw_fd()];
+            if state.flags[&offset] & GPIO_V2_LINE_FLAG_OUTPUT == 0 {
+                return Err(io::Error::from_raw_os_error(libc::EPERM));
+            }
+            state.values.insert(offset, values.bits & 1 == 1);
+            Ok(())
+        }
+    }
+
+    impl FakeChip {
+        fn set_flags(&self, offset: u32, config: &LineConfig) {
+            let mut state = self.0.borrow_mut();
+            state.flags.insert(offset, config.flags);
+            if config.num_attrs == 1 {
+                state.values.insert(offset, config.attrs[0].attr.value == 1);
+            }
+        }
+    }
+
+    fn fake_gpio() -> (
+        CdevGpio,
+        Rc<RefCell<FakeChipState>>,
+        assert_fs::NamedTempFile,
+    ) {
+        let chip_file = assert_fs::NamedTempFile::new("gpiochip0").unwrap();
+        chip_file.touch().unwrap();
+        let state = Rc::new(RefCell::new(FakeChipState::default()));
+        let gpio = CdevGpio::with_io(
+            File::open(chip_file.path()).unwrap(),
+            Box::new(FakeChip(state.clone())),
+        )
+        .unwrap();
+        (gpio, state, chip_file)
+    }
+
+    #[test]
+    fn test_ioctl_numbers_match_kernel_headers() {
+        assert_eq!(GPIO_GET_CHIPINFO_IOCTL, 0x8044_B401);
+        assert_eq!(GPIO_V2_GET_LINE_IOCTL, 0xC250_B407);
+        assert_eq!(GPIO_V2_LINE_SET_CONFIG_IOCTL, 0xC110_B40D);
+        assert_eq!(GPIO_V2_LINE_GET_VALUES_IOCTL, 0xC010_B40E);
+        assert_eq!(GPIO_V2_LINE_SET_VALUES_IOCTL, 0xC010_B40F);
+    }
+
+    #[test]
+    fn test_cdev_line_configuration() {
+        let (mut gpio, state, _chip) = fake_gpio();
+        assert_eq!(gpio.num_lines(), 28);
+
+        gpio.write(18, true).unwrap();
+        assert_eq!(state.borrow().flags[&18], GPIO_V2_LINE_FLAG_OUTPUT);
+        assert!(state.borrow().values[&18]);
+        assert!(gpio.read(18).unwrap());
+
+        gpio.set_mode(17, PinMode::Input).unwrap();
+        gpio.set_pull(17, Pull::Up).unwrap();
+        assert_eq!(
+            state.borrow().flags[&17],
+            GPIO_V2_LINE_FLAG_INPUT | GPIO_V2_LINE_FLAG_BIAS_PULL_UP
+        );
+        assert!(gpio.write(17, true).is_err());
+
+        assert!(gpio.set_mode(14, PinMode::Alt(0)).is_err());
+        assert!(gpio.write(40, true).is_err());
+    }
+
+    #[test]
+    fn test_cdev_edge_events() {
+        let (mut gpio, state, _chip) = fake_gpio();
+        gpio.subscribe(4, Edge::Both).unwrap();
+        assert_eq!(
+            state.borrow().flags[&4],
+            GPIO_V2_LINE_FLAG_INPUT
+                | GPIO_V2_LINE_FLAG_EDGE_RISING
+                | GPIO_V2_LINE_FLAG_EDGE_FALLING
+        );
+
+        let timeout = Duration::from_millis(10);
+        assert!(gpio.wait_for_edge(timeout).unwrap().is_none());
+        // Sub-millisecond timeouts round up instead of polling without waiting
+        let start = Instant::now();
+        assert!(gpio
+            .wait_for_edge(Duration::from_micros(500))
+            .unwrap()
+            .is_none());
+        assert!(start.elapsed() >= Duration::from_micros(500));
+        assert_eq!(poll_timeout_ms(Duration::from_secs(u64::MAX)), i32::MAX);
+
+        let event = LineEvent {
+            timestamp_ns: 1_500,
+            id: 2,
+            offset: 4,
+            ..Default::default()
+        };
+        // SAFETY: `LineEvent` is plain old data
+        let bytes: [u8; size_of::<LineEvent>()] = unsafe { std::mem::transmute(event) };
+        (&state.borrow().event_writers[&4])
+            .write_all(&bytes)
+            .unwrap();
+
+        let received = gpio.wait_for_edge(timeout).unwrap().unwrap();
+        assert_eq!(received.pin, 4);
+        assert_eq!(received.edge, Edge::Falling);
+        assert_eq!(received.timestamp, Duration::from_nanos(1_500));
+
+        gpio.unsubscribe(4).unwrap();
+        assert_eq!(state.borrow().flags[&4], GPIO_V2_LINE_FLAG_INPUT);
+    }
+}
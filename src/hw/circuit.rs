@@ -0,0 +1,260 @@
+//! Virtual wiring between mock GPIO pins
+//!
+//! A [`Circuit`] joins pins into nets, within one `MockGpio` or across
+//! several attached with `MockGpio::set_circuit`. Each net's level is resolved
+//! from what its pins do: outputs drive it, and with no output driving, pull
+//! resistors decide or it floats. Two outputs driving opposite levels are
+//! recorded as contention and make the net unreadable.
+
+use super::Pull;
+use anyhow::Result;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// What a pin does to the net it is on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Drive {
+    Output(bool),
+    /// High impedance, with an optional pull resistor
+    Input(Pull),
+}
+
+impl Default for Drive {
+    fn default() -> Self {
+        Drive::Input(Pull::None)
+    }
+}
+
+/// Resolved state of a net
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetState {
+    /// At least one output drives the net and all outputs agree
+    Driven(bool),
+    /// No output drives the net; pull resistors hold it at a level
+    Pulled(bool),
+    /// Nothing drives the net, or pull-up and pull-down fight
+    Floating,
+    /// Outputs drive opposite levels
+    Contention,
+}
+
+impl NetState {
+    /// The level a reader sees, if the net has one
+    pub fn level(self) -> Option<bool> {
+        match self {
+            NetState::Driven(level) | NetState::Pulled(level) => Some(level),
+            NetState::Floating | NetState::Contention => None,
+        }
+    }
+}
+
+/// A pin on a named mock, e.g. `("board-a", 17)`
+pub type Endpoint = (String, u8);
+
+/// Two or more outputs driving opposite levels onto one net
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Contention {
+    pub net: String,
+    /// Time on the clock of the mock whose pin caused it
+    pub at: Duration,
+    /// The output pins and the levels they drove
+    pub drivers: Vec<(Endpoint, bool)>,
+}
+
+#[derive(Debug, Default)]
+struct CircuitState {
+    nets: BTreeMap<String, Vec<Endpoint>>,
+    net_of: HashMap<Endpoint, String>,
+    drives: HashMap<Endpoint, Drive>,
+    states: HashMap<String, NetState>,
+    /// Level changes not yet picked up by the mock owning each pin
+    pending: HashMap<String, Vec<(Duration, u8, NetState)>>,
+    contentions: Vec<Contention>,
+}
+
+impl CircuitState {
+    fn resolve(&self, net: &str) -> NetState {
+        let mut driven = None;
+        let mut pulls = (false, false);
+        for endpoint in &self.nets[net] {
+            match self.drives.get(endpoint).copied().unwrap_or_default() {
+                Drive::Output(level) => match driven {
+                    Some(other) if other != level => return NetState::Contention,
+                    _ => driven = Some(level),
+                },
+                Drive::Input(Pull::Up) => pulls.0 = true,
+                Drive::Input(Pull::Down) => pulls.1 = true,
+                Drive::Input(Pull::None) => {}
+            }
+        }
+        match (driven, pulls) {
+            (Some(level), _) => NetState::Driven(level),
+            (None, (true, false)) => NetState::Pulled(true),
+            (None, (false, true)) => NetState::Pulled(false),
+            (None, _) => NetState::Floating,
+        }
+    }
+
+    /// Re-resolve a net and tell its pins if the state changed
+    fn update(&mut self, net: &str, at: Duration) {
+        let state = self.resolve(net);
+        let previous = self.states.insert(net.to_string(), state);
+        if previous == Some(state) {
+            return;
+        }
+
+        if state == NetState::Contention {
+            let drivers: Vec<(Endpoint, bool)> = self.nets[net]
+                .iter()
+                .filter_map(|endpoint| match self.drives.get(endpoint) {
+                    Some(Drive::Output(level)) => Some((endpoint.clone(), *level)),
+                    _ => None,
+                })
+                .collect();
+            log::warn!("Contention on net {}: {:?}", net, drivers);
+            self.contentions.push(Contention {
+                net: net.to_string(),
+                at,
+                drivers,
+            });
+        }
+
+        for (mock, pin) in &self.nets[net] {
+            self.pending
+                .entry(mock.clone())
+                .or_default()
+                .push((at, *pin, state));
+        }
+    }
+}
+
+/// Shared wiring between mock GPIO pins; clones refer to the same circuit
+#[derive(Debug, Clone, Default)]
+pub struct Circuit {
+    state: Arc<Mutex<CircuitState>>,
+}
+
+impl Circuit {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, CircuitState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Join pins into a net, each given as the mock's circuit name and a pin number
+    ///
+    /// Fails if the net name is taken or a pin is already on a net.
+    pub fn connect(&self, net: &str, pins: &[(&str, u8)]) -> Result<()> {
+        let mut state = self.lock();
+        if state.nets.contains_key(net) {
+            return Err(anyhow::anyhow!("Net {} already exists", net));
+        }
+        let endpoints: Vec<Endpoint> = pins
+            .iter()
+            .map(|&(mock, pin)| (mock.to_string(), pin))
+            .collect();
+        for endpoint in &endpoints {
+            if let Some(other) = state.net_of.get(endpoint) {
+                return Err(anyhow::anyhow!(
+                    "Pin {} of {} is already on net {}",
+                    endpoint.1,
+                    endpoint.0,
+                    other
+                ));
+            }
+        }
+
+        for endpoint in &endpoints {
+            state.net_of.insert(endpoint.clone(), net.to_string());
+        }
+        state.nets.insert(net.to_string(), endpoints);
+        state.update(net, Duration::ZERO);
+        Ok(())
+    }
+
+    /// Get the resolved state of a net, if it exists
+    pub fn net_state(&self, net: &str) -> Option<NetState> {
+        self.lock().states.get(net).copied()
+    }
+
+    /// Get every contention so far, in order
+    pub fn get_contentions(&self) -> Vec<Contention> {
+        self.lock().contentions.clone()
+    }
+
+    /// Record what a pin now does to its net
+    pub(crate) fn drive(&self, mock: &str, pin: u8, drive: Drive, at: Duration) {
+        let mut state = self.lock();
+        let endpoint = (mock.to_string(), pin);
+        if state.drives.insert(endpoint.clone(), drive) == Some(drive) {
+            return;
+        }
+        if let Some(net) = state.net_of.get(&endpoint).cloned() {
+            state.update(&net, at);
+        }
+    }
+
+    /// Stop every pin of a mock from driving, as after a reset
+    pub(crate) fn release(&self, mock: &str, at: Duration) {
+        let mut state = self.lock();
+        state.drives.retain(|(owner, _), _| owner != mock);
+        let nets: Vec<String> = state
+            .net_of
+            .iter()
+            .filter(|((owner, _), _)| owner == mock)
+            .map(|(_, net)| net.clone())
+            .collect();
+        for net in nets {
+            state.update(&net, at);
+        }
+    }
+
+    /// Get the state of the net a pin is on, or `None` if it is not wired
+    pub(crate) fn state_at(&self, mock: &str, pin: u8) -> Option<NetState> {
+        let state = self.lock();
+        let net = state.net_of.get(&(mock.to_string(), pin))?;
+        state.states.get(net).copied()
+    }
+
+    /// Take the net changes a mock has not seen yet
+    pub(crate) fn take_changes(&self, mock: &str) -> Vec<(Duration, u8, NetState)> {
+        self.lock().pending.remove(mock).unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_net_resolution_and_contention() {
+        let circuit = Circuit::new();
+        circuit
+            .connect("bus", &[("a", 1), ("a", 2), ("b", 1)])
+            .unwrap();
+        assert!(circuit.connect("other", &[("b", 1)]).is_err());
+        assert_eq!(circuit.net_state("bus"), Some(NetState::Floating));
+
+        circuit.drive("b", 1, Drive::Input(Pull::Up), Duration::ZERO);
+        assert_eq!(circuit.net_state("bus"), Some(NetState::Pulled(true)));
+        circuit.drive("a", 2, Drive::Input(Pull::Down), Duration::ZERO);
+        assert_eq!(circuit.net_state("bus"), Some(NetState::Floating));
+
+        circuit.drive("a", 1, Drive::Output(false), Duration::from_micros(5));
+        assert_eq!(circuit.net_state("bus"), Some(NetState::Driven(false)));
+        circuit.drive("a", 2, Drive::Output(true), Duration::from_micros(9));
+        assert_eq!(circuit.net_state("bus"), Some(NetState::Contention));
+
+        let contentions = circuit.get_contentions();
+        assert_eq!(contentions.len(), 1);
+        assert_eq!(contentions[0].at, Duration::from_micros(9));
+        assert_eq!(contentions[0].drivers.len(), 2);
+        assert_eq!(circuit.take_changes("b").len(), 5);
+        assert!(circuit.take_changes("b").is_empty());
+    }
+}
@@ -0,0 +1,125 @@
+//! Time sources and delays
+//!
+//! Code that waits or timestamps takes a [`Clock`] or [`Delay`] instead of
+//! calling `std::time` directly. On hardware that is a [`SystemClock`]; in
+//! tests a [`VirtualClock`] stands still until the test advances it, so
+//! timing-dependent behaviour is deterministic and runs instantly.
+
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Source of the current time, measured from the clock's own epoch
+pub trait Clock: Debug + Send + Sync {
+    fn now(&self) -> Duration;
+}
+
+/// Blocking delays, as used by bit-banged protocols and conversion waits
+pub trait Delay {
+    fn delay(&mut self, duration: Duration);
+
+    fn delay_us(&mut self, us: u64) {
+        self.delay(Duration::from_micros(us));
+    }
+
+    fn delay_ms(&mut self, ms: u64) {
+        self.delay(Duration::from_millis(ms));
+    }
+}
+
+/// Wall-clock time since creation; clones share the epoch
+#[derive(Debug, Clone, Copy)]
+pub struct SystemClock {
+    epoch: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> Self {
+        Self {
+            epoch: Instant::now(),
+        }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.epoch.elapsed()
+    }
+}
+
+impl Delay for SystemClock {
+    fn delay(&mut self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
+/// Simulated time that only moves when advanced
+///
+/// Clones share the same time, so one clock handed to several mocks and a
+/// trace keeps them all in step. Delaying on it advances it.
+#[derive(Debug, Clone, Default)]
+pub struct VirtualClock {
+    now: Arc<Mutex<Duration>>,
+}
+
+impl VirtualClock {
+    /// Create a clock starting at zero
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Duration> {
+        self.now
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.lock() += duration;
+    }
+
+    /// Move the clock forward to `at`; earlier times leave it unchanged
+    pub fn advance_to(&self, at: Duration) {
+        let mut now = self.lock();
+        *now = (*now).max(at);
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> Duration {
+        *self.lock()
+    }
+}
+
+impl Delay for VirtualClock {
+    fn delay(&mut self, duration: Duration) {
+        self.advance(duration);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_virtual_clock_is_shared_and_advanced_by_delays() {
+        let clock = VirtualClock::new();
+        let mut delay = clock.clone();
+        assert_eq!(clock.now(), Duration::ZERO);
+
+        delay.delay_ms(5);
+        delay.delay_us(250);
+        assert_eq!(clock.now(), Duration::from_micros(5_250));
+
+        clock.advance_to(Duration::from_millis(2));
+        assert_eq!(clock.now(), Duration::from_micros(5_250));
+        clock.advance_to(Duration::from_millis(10));
+        assert_eq!(delay.now(), Duration::from_millis(10));
+    }
+}
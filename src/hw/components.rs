@@ -0,0 +1,271 @@
+//! Simulated parts wired to `MockGpio` pins
+//!
+//! Input parts (buttons, PIR sensors) drive their pin with a [`Waveform`]
+//! from the mock's current time, so presses bounce and detections time out as
+//! the clock advances. Output parts (LEDs, relays) read back what the driver
+//! wrote from the mock's level history. Run the mock on a `VirtualClock` to
+//! control when things happen.
+
+use super::{LevelChange, MockGpio, Waveform};
+use std::time::Duration;
+
+/// Level changes of one pin, in time order
+fn pin_changes(gpio: &MockGpio, pin: u8) -> Vec<LevelChange> {
+    gpio.get_level_changes()
+        .into_iter()
+        .filter(|change| change.pin == pin)
+        .collect()
+}
+
+/// Push button on an input pin
+///
+/// Pressed reads high unless the button is made active low, as when it
+/// shorts a pulled-up pin to ground.
+#[derive(Debug, Clone)]
+pub struct Button {
+    pin: u8,
+    active_low: bool,
+    bounce: Option<(Duration, u64)>,
+    presses: usize,
+}
+
+impl Button {
+    pub fn new(pin: u8) -> Self {
+        Self {
+            pin,
+            active_low: false,
+            bounce: None,
+            presses: 0,
+        }
+    }
+
+    pub fn active_low(mut self) -> Self {
+        self.active_low = true;
+        self
+    }
+
+    /// Bounce for up to `settle` on every press and release
+    ///
+    /// Each change bounces differently, but the same seed replays the same run.
+    pub fn with_bounce(mut self, settle: Duration, seed: u64) -> Self {
+        self.bounce = Some((settle, seed));
+        self
+    }
+
+    /// Press the button now
+    pub fn press(&mut self, gpio: &mut MockGpio) {
+        self.presses += 1;
+        self.set(gpio, true);
+    }
+
+    /// Release the button now
+    pub fn release(&mut self, gpio: &mut MockGpio) {
+        self.set(gpio, false);
+    }
+
+    pub fn get_press_count(&self) -> usize {
+        self.presses
+    }
+
+    fn set(&self, gpio: &mut MockGpio, pressed: bool) {
+        let level = pressed != self.active_low;
+        let waveform = match self.bounce {
+            Some((settle, seed)) => {
+                // A fresh seed per change: press n adds 2n - 1, the release after it 2n
+                let change = (self.presses * 2 - usize::from(pressed)) as u64;
+                Waveform::bounce(!level, gpio.get_time(), settle, seed.wrapping_add(change))
+            }
+            None => Waveform::constant(level),
+        };
+        gpio.set_input_waveform(self.pin, waveform);
+    }
+}
+
+/// LED on an output pin
+#[derive(Debug, Clone)]
+pub struct Led {
+    pin: u8,
+    active_low: bool,
+}
+
+impl Led {
+    pub fn new(pin: u8) -> Self {
+        Self {
+            pin,
+            active_low: false,
+        }
+    }
+
+    /// The LED lights when the pin is driven low (wired to the supply)
+    pub fn active_low(mut self) -> Self {
+        self.active_low = true;
+        self
+    }
+
+    pub fn is_on(&self, gpio: &MockGpio) -> bool {
+        pin_changes(gpio, self.pin)
+            .last()
+            .is_some_and(|change| change.high != self.active_low)
+    }
+
+    /// Get the total time the LED has been lit, up to now
+    pub fn get_on_time(&self, gpio: &MockGpio) -> Duration {
+        let mut on_time = Duration::ZERO;
+        let mut lit_since = None;
+        for change in pin_changes(gpio, self.pin) {
+            let on = change.high != self.active_low;
+            match (on, lit_since) {
+                (true, None) => lit_since = Some(change.at),
+                (false, Some(since)) => {
+                    on_time += change.at - since;
+                    lit_since = None;
+                }
+                _ => {}
+            }
+        }
+        if let Some(since) = lit_since {
+            on_time += gpio.get_time().saturating_sub(since);
+        }
+        on_time
+    }
+
+    /// Get the number of times the LED has turned on
+    pub fn get_flash_count(&self, gpio: &MockGpio) -> usize {
+        let mut lit = false;
+        let mut flashes = 0;
+        for change in pin_changes(gpio, self.pin) {
+            let on = change.high != self.active_low;
+            if on && !lit {
+                flashes += 1;
+            }
+            lit = on;
+        }
+        flashes
+    }
+}
+
+/// Relay whose coil is driven by an output pin
+///
+/// The contacts follow the coil after the switching time, on both make and
+/// break.
+#[derive(Debug, Clone)]
+pub struct Relay {
+    coil_pin: u8,
+    switch_time: Duration,
+}
+
+impl Relay {
+    pub fn new(coil_pin: u8, switch_time: Duration) -> Self {
+        Self {
+            coil_pin,
+            switch_time,
+        }
+    }
+
+    /// Coil energised changes that have reached the contacts by now
+    fn settled_changes(&self, gpio: &MockGpio) -> Vec<bool> {
+        let now = gpio.get_time();
+        pin_changes(gpio, self.coil_pin)
+            .into_iter()
+            .filter(|change| change.at + self.switch_time <= now)
+            .map(|change| change.high)
+            .collect()
+    }
+
+    pub fn is_closed(&self, gpio: &MockGpio) -> bool {
+        self.settled_changes(gpio).last().copied().unwrap_or(false)
+    }
+
+    /// Get the number of times the contacts have closed
+    pub fn get_close_count(&self, gpio: &MockGpio) -> usize {
+        let mut closed = false;
+        let mut closes = 0;
+        for energised in self.settled_changes(gpio) {
+            if energised && !closed {
+                closes += 1;
+            }
+            closed = energised;
+        }
+        closes
+    }
+}
+
+/// PIR motion sensor on an input pin
+///
+/// Motion drives the output high for the hold time; motion while it is high
+/// extends it, as with a retriggering sensor.
+#[derive(Debug, Clone)]
+pub struct Pir {
+    pin: u8,
+    hold: Duration,
+    /// Current or last detection as (rise, fall)
+    active: Option<(Duration, Duration)>,
+    detections: usize,
+}
+
+impl Pir {
+    pub fn new(pin: u8, hold: Duration) -> Self {
+        Self {
+            pin,
+            hold,
+            active: None,
+            detections: 0,
+        }
+    }
+
+    /// Detect motion now
+    pub fn motion(&mut self, gpio: &mut MockGpio) {
+        let now = gpio.get_time();
+        let rise = match self.active {
+            Some((rise, fall)) if fall > now => rise,
+            _ => {
+                self.detections += 1;
+                now
+            }
+        };
+        let fall = now + self.hold;
+        self.active = Some((rise, fall));
+        gpio.set_input_waveform(
+            self.pin,
+            Waveform::steps(false, vec![(rise, true), (fall, false)]),
+        );
+    }
+
+    /// Get the number of separate detections (retriggers not counted)
+    pub fn get_detection_count(&self) -> usize {
+        self.detections
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hw::{Gpio, PinMode, VirtualClock};
+
+    #[test]
+    fn test_led_and_relay_follow_writes() {
+        let clock = VirtualClock::new();
+        let mut gpio = MockGpio::new();
+        gpio.set_clock(clock.clone());
+        gpio.set_mode(18, PinMode::Output).unwrap();
+        gpio.set_mode(23, PinMode::Output).unwrap();
+        let led = Led::new(18);
+        let relay = Relay::new(23, Duration::from_millis(10));
+
+        gpio.write(18, true).unwrap();
+        gpio.write(23, true).unwrap();
+        clock.advance(Duration::from_millis(5));
+        assert!(led.is_on(&gpio));
+        assert!(!relay.is_closed(&gpio));
+
+        gpio.write(18, false).unwrap();
+        clock.advance(Duration::from_millis(5));
+        assert!(relay.is_closed(&gpio));
+        gpio.write(18, true).unwrap();
+        clock.advance(Duration::from_millis(1));
+
+        assert_eq!(led.get_on_time(&gpio), Duration::from_millis(6));
+        assert_eq!(led.get_flash_count(&gpio), 2);
+        assert_eq!(relay.get_close_count(&gpio), 1);
+    }
+}
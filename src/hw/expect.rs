@@ -0,0 +1,196 @@
+//! Expected operations for strict mocks
+//!
+//! A mock given a list of expectations checks every call against the next
+//! one as it happens: the first call that does not match fails with the
+//! expected and actual operation side by side, and `done()` reports
+//! expectations that were never reached.
+
+use super::{ChipSelect, I2cOpRecord, PinMode, Pull, SpiOpRecord};
+use std::collections::VecDeque;
+use std::fmt::Debug;
+
+/// Expected GPIO call; `Read` carries the level to return
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GpioExpectation {
+    SetMode(u8, PinMode),
+    SetPull(u8, Pull),
+    Write(u8, bool),
+    Read(u8, bool),
+}
+
+/// Expected I2C call; read data is the reply to return
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum I2cExpectation {
+    Write {
+        address: u8,
+        data: Vec<u8>,
+    },
+    Read {
+        address: u8,
+        reply: Vec<u8>,
+    },
+    /// Combined transaction; `I2cOpRecord::Read` steps hold the bytes to return
+    Transaction {
+        address: u8,
+        operations: Vec<I2cOpRecord>,
+    },
+}
+
+impl I2cExpectation {
+    /// The transaction issued by `I2c::write_read`
+    pub fn write_read(address: u8, data: Vec<u8>, reply: Vec<u8>) -> Self {
+        I2cExpectation::Transaction {
+            address,
+            operations: vec![I2cOpRecord::Write(data), I2cOpRecord::Read(reply)],
+        }
+    }
+}
+
+/// Expected SPI chip-select assertion
+///
+/// `Read` and the `received` half of `Transfer` hold the bytes to return.
+/// Leaving `target` as `None` accepts any selected device.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpiExpectation {
+    pub target: Option<ChipSelect>,
+    pub operations: Vec<SpiOpRecord>,
+}
+
+impl SpiExpectation {
+    pub fn transaction(operations: Vec<SpiOpRecord>) -> Self {
+        Self {
+            target: None,
+            operations,
+        }
+    }
+
+    /// The single full-duplex segment issued by `Spi::transfer`
+    pub fn transfer(sent: Vec<u8>, received: Vec<u8>) -> Self {
+        Self::transaction(vec![SpiOpRecord::Transfer { sent, received }])
+    }
+
+    pub fn write(data: Vec<u8>) -> Self {
+        Self::transaction(vec![SpiOpRecord::Write(data)])
+    }
+
+    pub fn read(reply: Vec<u8>) -> Self {
+        Self::transaction(vec![SpiOpRecord::Read(reply)])
+    }
+
+    /// The transaction issued by `Spi::write_read`
+    pub fn write_read(data: Vec<u8>, reply: Vec<u8>) -> Self {
+        Self::transaction(vec![SpiOpRecord::Write(data), SpiOpRecord::Read(reply)])
+    }
+
+    /// Require the transaction to go to a particular device
+    pub fn on(mut self, cs: ChipSelect) -> Self {
+        self.target = Some(cs);
+        self
+    }
+}
+
+/// Queue of expectations a strict mock consumes in order
+#[derive(Debug, Clone)]
+pub(crate) struct Expectations<E> {
+    bus: &'static str,
+    pending: VecDeque<E>,
+    consumed: usize,
+    /// First mismatch, kept so `done()` fails even if the driver swallowed the error
+    failure: Option<String>,
+}
+
+impl<E: Debug> Expectations<E> {
+    pub(crate) fn new(bus: &'static str, expectations: Vec<E>) -> Self {
+        Self {
+            bus,
+            pending: expectations.into(),
+            consumed: 0,
+            failure: None,
+        }
+    }
+
+    /// Consume the next expectation if `accept` returns a reply for it
+    ///
+    /// `actual` describes the call for the error message.
+    pub(crate) fn next<R>(
+        &mut self,
+        actual: String,
+        accept: impl FnOnce(&E) -> Option<R>,
+    ) -> Result<R, String> {
+        if let Some(reply) = self.pending.front().and_then(accept) {
+            self.pending.pop_front();
+            self.consumed += 1;
+            return Ok(reply);
+        }
+
+        let expected = match self.pending.front() {
+            Some(expectation) => format!("{:?}", expectation),
+            None => "no further operations".to_string(),
+        };
+        let message = format!(
+            "Unexpected {} operation #{}\n  expected: {}\n    actual: {}",
+            self.bus,
+            self.consumed + 1,
+            expected,
+            actual
+        );
+        self.failure.get_or_insert_with(|| message.clone());
+        Err(message)
+    }
+
+    /// Look at the next expectation without consuming it
+    pub(crate) fn peek(&self) -> Option<&E> {
+        self.pending.front()
+    }
+
+    pub(crate) fn done(&self) -> Result<(), String> {
+        if let Some(failure) = &self.failure {
+            return Err(failure.clone());
+        }
+        if let Some(next) = self.pending.front() {
+            return Err(format!(
+                "{} of {} {} expectations not met, next: {:?}",
+                self.pending.len(),
+                self.consumed + self.pending.len(),
+                self.bus,
+                next
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_expectations_report_mismatch_and_leftovers() {
+        let mut queue = Expectations::new(
+            "GPIO",
+            vec![
+                GpioExpectation::Write(4, true),
+                GpioExpectation::Read(5, false),
+            ],
+        );
+        let write = GpioExpectation::Write(4, true);
+        assert!(queue
+            .next(format!("{:?}", write), |e| (*e == write).then_some(()))
+            .is_ok());
+
+        let err = queue
+            .next("Write(5, true)".to_string(), |_| None::<()>)
+            .unwrap_err();
+        assert_eq!(
+            err,
+            "Unexpected GPIO operation #2\n  expected: Read(5, false)\n    actual: Write(5, true)"
+        );
+        assert_eq!(queue.done().unwrap_err(), err);
+
+        let leftovers = Expectations::new("SPI", vec![SpiExpectation::write(vec![0x06])]);
+        assert!(leftovers
+            .done()
+            .unwrap_err()
+            .starts_with("1 of 1 SPI expectations not met"));
+    }
+}
@@ -0,0 +1,228 @@
+//! Scheduled and random fault injection for mocks
+//!
+//! A [`FaultPlan`] is a list of rules, each pairing a [`Trigger`] (which
+//! operations it hits) with a [`Fault`] (what happens to them). Give one to a
+//! mock with `set_fault_plan`; every operation the mock handles is counted and
+//! checked against the rules in order. Random triggers draw from a generator
+//! seeded by the plan, so a failing run can be replayed exactly.
+
+use super::rng::SeededRng;
+use super::I2cError;
+use std::collections::HashSet;
+
+/// Which operations a rule hits, counting from 1
+#[derive(Debug, Clone, PartialEq)]
+pub enum Trigger {
+    /// Only the nth operation
+    Nth(usize),
+    /// Every nth operation
+    Every(usize),
+    /// Each operation independently, with probability `p`
+    Probability(f64),
+    /// Bursts of `length` consecutive operations, each starting with probability `p`
+    Bursts {
+        p: f64,
+        length: usize,
+    },
+    Always,
+}
+
+/// What happens to an operation a rule hits
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Fault {
+    /// Fail with a generic injected error
+    Fail,
+    /// Fail with a specific I2C error; GPIO and SPI mocks reject plans using it
+    FailWith(I2cError),
+    /// Flip this many distinct random bits in the data read back; only hits
+    /// operations that read, and inverts a GPIO read
+    FlipBits(u32),
+}
+
+impl Fault {
+    /// The error an operation hit by this fault fails with, or `None` if it corrupts data instead
+    pub(crate) fn error(&self, target: &str) -> Option<I2cError> {
+        match self {
+            Fault::Fail => Some(I2cError::Other(format!("Injected fault on {}", target))),
+            Fault::FailWith(error) => Some(error.clone()),
+            Fault::FlipBits(_) => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Rule {
+    trigger: Trigger,
+    fault: Fault,
+    /// Operations left in the current burst
+    burst_left: usize,
+}
+
+/// Seeded list of fault rules for one mock
+#[derive(Debug, Clone)]
+pub struct FaultPlan {
+    rules: Vec<Rule>,
+    rng: SeededRng,
+    operations: usize,
+    injected: usize,
+}
+
+impl FaultPlan {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rules: Vec::new(),
+            rng: SeededRng::new(seed),
+            operations: 0,
+            injected: 0,
+        }
+    }
+
+    /// Add a rule; when several hit the same operation the first one added wins
+    pub fn with(mut self, trigger: Trigger, fault: Fault) -> Self {
+        self.rules.push(Rule {
+            trigger,
+            fault,
+            burst_left: 0,
+        });
+        self
+    }
+
+    /// Get the number of operations seen so far
+    pub fn get_operation_count(&self) -> usize {
+        self.operations
+    }
+
+    /// Get the number of faults injected so far
+    pub fn get_injected_count(&self) -> usize {
+        self.injected
+    }
+
+    /// Fail unless every rule's fault can happen on `bus`, which is not I2C
+    pub(crate) fn check_bus(&self, bus: &str) -> anyhow::Result<()> {
+        match self
+            .rules
+            .iter()
+            .find(|rule| matches!(rule.fault, Fault::FailWith(_)))
+        {
+            Some(rule) => Err(anyhow::anyhow!(
+                "{:?} only applies to I2C, not {}",
+                rule.fault,
+                bus
+            )),
+            None => Ok(()),
+        }
+    }
+
+    /// Count an operation and decide whether it is hit
+    ///
+    /// Every trigger is evaluated on every operation so the random sequence
+    /// does not depend on which rule fired.
+    pub(crate) fn next(&mut self, reads_data: bool) -> Option<Fault> {
+        self.operations += 1;
+        let n = self.operations;
+        let mut hit = None;
+        for rule in &mut self.rules {
+            let fires = match rule.trigger {
+                Trigger::Nth(nth) => n == nth,
+                Trigger::Every(every) => every > 0 && n % every == 0,
+                Trigger::Probability(p) => self.rng.chance(p),
+                Trigger::Bursts { p, length } => {
+                    if rule.burst_left == 0 && self.rng.chance(p) {
+                        rule.burst_left = length;
+                    }
+                    let in_burst = rule.burst_left > 0;
+                    rule.burst_left = rule.burst_left.saturating_sub(1);
+                    in_burst
+                }
+                Trigger::Always => true,
+            };
+            let applies = reads_data || !matches!(rule.fault, Fault::FlipBits(_));
+            if fires && applies && hit.is_none() {
+                hit = Some(rule.fault.clone());
+            }
+        }
+        if hit.is_some() {
+            self.injected += 1;
+        }
+        hit
+    }
+
+    /// Flip `flips` distinct random bits across the buffers read back, or
+    /// every bit if there are fewer
+    pub(crate) fn corrupt(&mut self, buffers: &mut [&mut [u8]], flips: u32) {
+        let bits: usize = buffers.iter().map(|buffer| buffer.len() * 8).sum();
+        let flips = (flips as usize).min(bits);
+
+        // Floyd's sampling: one draw per bit, never the same bit twice
+        let mut chosen = HashSet::with_capacity(flips);
+        for limit in bits - flips..bits {
+            let bit = self.rng.below(limit as u64 + 1) as usize;
+            if !chosen.insert(bit) {
+                chosen.insert(limit);
+            }
+        }
+
+        for mut bit in chosen {
+            for buffer in buffers.iter_mut() {
+                if bit < buffer.len() * 8 {
+                    buffer[bit / 8] ^= 1 << (bit % 8);
+                    break;
+                }
+                bit -= buffer.len() * 8;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fault_plan_triggers() {
+        let mut plan = FaultPlan::new(1)
+            .with(Trigger::Nth(2), Fault::Fail)
+            .with(Trigger::Every(3), Fault::FlipBits(1));
+        let hits: Vec<_> = (0..6).map(|_| plan.next(false)).collect();
+        assert_eq!(hits, vec![None, Some(Fault::Fail), None, None, None, None]);
+        assert_eq!(plan.next(true), None);
+        assert_eq!(plan.next(true), None);
+        assert_eq!(plan.next(true), Some(Fault::FlipBits(1)));
+        assert_eq!(plan.get_injected_count(), 2);
+
+        let run = |seed| {
+            let mut plan =
+                FaultPlan::new(seed).with(Trigger::Bursts { p: 0.2, length: 3 }, Fault::Fail);
+            (0..200)
+                .map(|_| plan.next(false).is_some())
+                .collect::<Vec<_>>()
+        };
+        let hits = run(7);
+        assert_eq!(hits, run(7));
+        assert!(hits.iter().any(|hit| *hit) && !hits.iter().all(|hit| *hit));
+        // Bursts may run back to back, so every run of hits is whole bursts
+        let runs: Vec<usize> = hits
+            .split(|hit| !*hit)
+            .map(|run| run.len())
+            .filter(|&len| len > 0)
+            .collect();
+        assert!(runs[..runs.len() - 1].iter().all(|len| len % 3 == 0));
+
+        let mut data = [0u8; 4];
+        let mut other = [0u8; 2];
+        plan.corrupt(&mut [&mut data, &mut other], 1);
+        let flipped: u32 = data.iter().chain(&other).map(|b| b.count_ones()).sum();
+        assert_eq!(flipped, 1);
+
+        // Several flips on one byte never land on the same bit twice
+        for seed in 0..64 {
+            let mut plan = FaultPlan::new(seed);
+            let mut byte = [0u8; 1];
+            plan.corrupt(&mut [&mut byte], 2);
+            assert_eq!(byte[0].count_ones(), 2);
+            // More flips than bits flips every bit once
+            plan.corrupt(&mut [&mut byte], 20);
+            assert_eq!(byte[0].count_ones(), 6);
+        }
+    }
+}
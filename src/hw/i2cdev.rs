@@ -0,0 +1,369 @@
+//! Linux i2c-dev backend (`/dev/i2c-N`)
+
+use super::{I2c, I2cError, I2cOperation, I2cResult};
+use anyhow::{Context, Result};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::fd::AsRawFd;
+use std::path::Path;
+
+const I2C_SLAVE: u64 = 0x0703;
+const I2C_RDWR: u64 = 0x0707;
+const I2C_M_RD: u16 = 0x0001;
+
+/// The kernel rejects I2C_RDWR requests with more messages than this
+const I2C_RDWR_IOCTL_MAX_MSGS: usize = 42;
+
+/// Map an adapter error to an `I2cError`, following the kernel's I2C fault codes
+///
+/// Some adapters (e.g. bcm2835) report any NACK as EREMOTEIO, so an absent
+/// device can show up as `DataNack` on those buses.
+pub(crate) fn bus_error(err: io::Error, address: u8) -> I2cError {
+    match err.raw_os_error() {
+        Some(libc::ENXIO) => I2cError::AddressNack(address),
+        Some(libc::EREMOTEIO) => I2cError::DataNack(address),
+        Some(libc::EAGAIN) => I2cError::ArbitrationLoss,
+        Some(libc::ETIMEDOUT) => I2cError::Timeout,
+        _ => I2cError::Other(format!(
+            "I2C transfer with address 0x{:02X} failed: {}",
+            address, err
+        )),
+    }
+}
+
+/// `struct i2c_msg`
+#[repr(C)]
+#[derive(Debug)]
+pub(crate) struct I2cMsg {
+    pub addr: u16,
+    pub flags: u16,
+    pub len: u16,
+    pub buf: *mut u8,
+}
+
+/// `struct i2c_rdwr_ioctl_data`
+#[repr(C)]
+struct RdwrIoctlData {
+    msgs: *mut I2cMsg,
+    nmsgs: u32,
+}
+
+/// Build the `i2c_msg` array for a combined transaction
+///
+/// The returned messages borrow the operation buffers through raw pointers and
+/// must not outlive `operations`.
+pub(crate) fn build_msgs(
+    address: u16,
+    operations: &mut [I2cOperation<'_>],
+) -> io::Result<Vec<I2cMsg>> {
+    if operations.len() > I2C_RDWR_IOCTL_MAX_MSGS {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "at most {} messages per transaction",
+                I2C_RDWR_IOCTL_MAX_MSGS
+            ),
+        ));
+    }
+
+    operations
+        .iter_mut()
+        .map(|operation| {
+            let (flags, len, buf) = match operation {
+                I2cOperation::Write(data) => (0, data.len(), data.as_ptr() as *mut u8),
+                I2cOperation::Read(buffer) => (I2C_M_RD, buffer.len(), buffer.as_mut_ptr()),
+            };
+            let len = u16::try_from(len)
+                .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "I2C message too long"))?;
+            Ok(I2cMsg {
+                addr: address,
+                flags,
+                len,
+                buf,
+            })
+        })
+        .collect()
+}
+
+/// The file and ioctl operations on an i2c-dev node, behind a seam for tests
+pub(crate) trait BusIo {
+    /// Select the target address for subsequent plain reads and writes
+    fn set_slave(&mut self, dev: &File, address: u16) -> io::Result<()>;
+    fn write(&mut self, dev: &File, data: &[u8]) -> io::Result<usize>;
+    fn read(&mut self, dev: &File, buffer: &mut [u8]) -> io::Result<usize>;
+    /// Run operations back to back with repeated starts and a single STOP
+    fn rdwr(
+        &mut self,
+        dev: &File,
+        address: u16,
+        operations: &mut [I2cOperation<'_>],
+    ) -> io::Result<()>;
+}
+
+/// `BusIo` backed by the real character device
+struct SysBusIo;
+
+impl BusIo for SysBusIo {
+    fn set_slave(&mut self, dev: &File, address: u16) -> io::Result<()> {
+        // SAFETY: I2C_SLAVE takes the address by value, not through a pointer
+        let ret = unsafe {
+            libc::ioctl(
+                dev.as_raw_fd(),
+                I2C_SLAVE as _,
+                libc::c_ulong::from(address),
+            )
+        };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, mut dev: &File, data: &[u8]) -> io::Result<usize> {
+        dev.write(data)
+    }
+
+    fn read(&mut self, mut dev: &File, buffer: &mut [u8]) -> io::Result<usize> {
+        dev.read(buffer)
+    }
+
+    fn rdwr(
+        &mut self,
+        dev: &File,
+        address: u16,
+        operations: &mut [I2cOperation<'_>],
+    ) -> io::Result<()> {
+        let mut msgs = build_msgs(address, operations)?;
+        let mut data = RdwrIoctlData {
+            msgs: msgs.as_mut_ptr(),
+            nmsgs: msgs.len() as u32,
+        };
+        // SAFETY: every message points into an operation buffer that is borrowed
+        // for the duration of the call, with `len` matching its size.
+        let ret = unsafe { libc::ioctl(dev.as_raw_fd(), I2C_RDWR as _, &mut data) };
+        if ret < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+/// `I2c` implementation on a Linux i2c-dev node
+pub struct I2cDev {
+    dev: File,
+    io: Box<dyn BusIo>,
+    current_address: Option<u8>,
+}
+
+impl I2cDev {
+    /// Open an i2c-dev node such as `/dev/i2c-1`
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let dev = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .with_context(|| format!("Failed to open I2C bus {}", path.display()))?;
+        Ok(Self::with_io(dev, Box::new(SysBusIo)))
+    }
+
+    /// Open `/dev/i2c-<bus>`
+    pub fn open_bus(bus: u8) -> Result<Self> {
+        Self::open(format!("/dev/i2c-{}", bus))
+    }
+
+    pub(crate) fn with_io(dev: File, io: Box<dyn BusIo>) -> Self {
+        Self {
+            dev,
+            io,
+            current_address: None,
+        }
+    }
+
+    fn select(&mut self, address: u8) -> I2cResult<()> {
+        if self.current_address != Some(address) {
+            self.io
+                .set_slave(&self.dev, u16::from(address))
+                .map_err(|e| {
+                    I2cError::Other(format!(
+                        "Failed to select I2C address 0x{:02X}: {}",
+                        address, e
+                    ))
+                })?;
+            self.current_address = Some(address);
+        }
+        Ok(())
+    }
+}
+
+impl I2c for I2cDev {
+    fn write(&mut self, address: u8, data: &[u8]) -> I2cResult<()> {
+        self.select(address)?;
+        let written = self
+            .io
+            .write(&self.dev, data)
+            .map_err(|e| bus_error(e, address))?;
+        if written != data.len() {
+            // The device stopped acknowledging part-way through
+            return Err(I2cError::DataNack(address));
+        }
+        Ok(())
+    }
+
+    fn read(&mut self, address: u8, buffer: &mut [u8]) -> I2cResult<usize> {
+        self.select(address)?;
+        self.io
+            .read(&self.dev, buffer)
+            .map_err(|e| bus_error(e, address))
+    }
+
+    fn transaction(&mut self, address: u8, operations: &mut [I2cOperation<'_>]) -> I2cResult<()> {
+        self.io
+            .rdwr(&self.dev, u16::from(address), operations)
+            .map_err(|e| bus_error(e, address))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::prelude::*;
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::rc::Rc;
+
+    /// Bus with scripted devices; unknown addresses NACK like the kernel reports them
+    #[derive(Default)]
+    struct FakeBusState {
+        slave: Option<u16>,
+        set_slave_calls: usize,
+        devices: HashMap<u16, Vec<u8>>,
+        writes: Vec<(u16, Vec<u8>)>,
+    }
+
+    struct FakeBus(Rc<RefCell<FakeBusState>>);
+
+    impl FakeBus {
+        fn reply(state: &FakeBusState, address: u16, buffer: &mut [u8]) -> io::Result<usize> {
+            let reply = state
+                .devices
+                .get(&address)
+                .ok_or_else(|| io::Error::from_raw_os_error(libc::ENXIO))?;
+            let n = buffer.len().min(reply.len());
+            buffer[..n].copy_from_slice(&reply[..n]);
+            Ok(n)
+        }
+    }
+
+    impl BusIo for FakeBus {
+        fn set_slave(&mut self, _dev: &File, address: u16) -> io::Result<()> {
+            let mut state = self.0.borrow_mut();
+            state.slave = Some(address);
+            state.set_slave_calls += 1;
+            Ok(())
+        }
+
+        fn write(&mut self, _dev: &File, data: &[u8]) -> io::Result<usize> {
+            let mut state = self.0.borrow_mut();
+            let address = state.slave.expect("address selected");
+            if !state.devices.contains_key(&address) {
+                return Err(io::Error::from_raw_os_error(libc::ENXIO));
+            }
+            state.writes.push((address, data.to_vec()));
+            Ok(data.len())
+        }
+
+        fn read(&mut self, _dev: &File, buffer: &mut [u8]) -> io::Result<usize> {
+            let state = self.0.borrow();
+            Self::reply(&state, state.slave.expect("address selected"), buffer)
+        }
+
+        fn rdwr(
+            &mut self,
+            _dev: &File,
+            address: u16,
+            operations: &mut [I2cOperation<'_>],
+        ) -> io::Result<()> {
+            let mut state = self.0.borrow_mut();
+            for operation in operations {
+                match operation {
+                    I2cOperation::Write(data) => state.writes.push((address, data.to_vec())),
+                    I2cOperation::Read(buffer) => {
+                        Self::reply(&state, address, buffer)?;
+                    }
+                }
+            }
+            Ok(())
+        }
+    }
+
+    fn fake_bus() -> (I2cDev, Rc<RefCell<FakeBusState>>, assert_fs::NamedTempFile) {
+        let node = assert_fs::NamedTempFile::new("i2c-1").unwrap();
+        node.touch().unwrap();
+        let state = Rc::new(RefCell::new(FakeBusState::default()));
+        let dev = I2cDev::with_io(
+            File::open(node.path()).unwrap(),
+            Box::new(FakeBus(state.clone())),
+        );
+        (dev, state, node)
+    }
+
+    #[test]
+    fn test_build_msgs_flags_and_lengths() {
+        let command = [0xF7];
+        let mut buffer = [0u8; 8];
+        let mut operations = [
+            I2cOperation::Write(&command),
+            I2cOperation::Read(&mut buffer),
+        ];
+        let msgs = build_msgs(0x76, &mut operations).unwrap();
+
+        assert_eq!(msgs.len(), 2);
+        assert_eq!((msgs[0].addr, msgs[0].flags, msgs[0].len), (0x76, 0, 1));
+        assert_eq!(
+            (msgs[1].addr, msgs[1].flags, msgs[1].len),
+            (0x76, I2C_M_RD, 8)
+        );
+
+        let long = vec![0u8; 70_000];
+        assert!(build_msgs(0x76, &mut [I2cOperation::Write(&long)]).is_err());
+    }
+
+    #[test]
+    fn test_i2cdev_addressing() {
+        let (mut dev, state, _node) = fake_bus();
+        state.borrow_mut().devices.insert(0x48, vec![0x12, 0x34]);
+
+        dev.write(0x48, &[0x01]).unwrap();
+        let mut buffer = [0u8; 2];
+        assert_eq!(dev.read(0x48, &mut buffer).unwrap(), 2);
+        assert_eq!(buffer, [0x12, 0x34]);
+
+        // I2C_SLAVE is only reissued when the address changes
+        assert_eq!(state.borrow().set_slave_calls, 1);
+        assert_eq!(dev.write(0x50, &[0x00]), Err(I2cError::AddressNack(0x50)));
+        assert_eq!(state.borrow().set_slave_calls, 2);
+    }
+
+    #[test]
+    fn test_bus_error_mapping() {
+        let err = |code| bus_error(io::Error::from_raw_os_error(code), 0x48);
+        assert_eq!(err(libc::ENXIO), I2cError::AddressNack(0x48));
+        assert_eq!(err(libc::EREMOTEIO), I2cError::DataNack(0x48));
+        assert_eq!(err(libc::EAGAIN), I2cError::ArbitrationLoss);
+        assert_eq!(err(libc::ETIMEDOUT), I2cError::Timeout);
+        assert!(matches!(err(libc::EIO), I2cError::Other(_)));
+    }
+
+    #[test]
+    fn test_i2cdev_write_read() {
+        let (mut dev, state, _node) = fake_bus();
+        state.borrow_mut().devices.insert(0x76, vec![0x60]);
+
+        let mut id = [0u8; 1];
+        dev.write_read(0x76, &[0xD0], &mut id).unwrap();
+        assert_eq!(id, [0x60]);
+        assert_eq!(state.borrow().writes, vec![(0x76, vec![0xD0])]);
+        assert_eq!(state.borrow().set_slave_calls, 0);
+    }
+}
@@ -0,0 +1,182 @@
+//! Register-map model of a typical I2C chip, for use with `MockI2c`
+
+use super::{I2cDevice, I2cResult};
+use std::collections::HashMap;
+
+/// How the bus may access a register
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum RegisterAccess {
+    #[default]
+    ReadWrite,
+    /// Writes are acknowledged but have no effect (e.g. chip ID)
+    ReadOnly,
+    /// Reads return zero (e.g. command or reset registers)
+    WriteOnly,
+    /// Reads return the value, then the register clears to zero (e.g. interrupt status)
+    ClearOnRead,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Register {
+    value: u8,
+    access: RegisterAccess,
+}
+
+/// Chip with 8-bit registers behind an 8-bit register pointer
+///
+/// The first byte of every write sets the pointer; further bytes are written
+/// to consecutive registers. Reads start at the pointer. With auto-increment
+/// enabled (the default) the pointer advances after every byte, wrapping at
+/// 0xFF. Registers that were never declared read as zero and accept writes.
+#[derive(Debug, Clone)]
+pub struct RegisterMap {
+    registers: HashMap<u8, Register>,
+    pointer: u8,
+    auto_increment: bool,
+    write_counts: HashMap<u8, usize>,
+}
+
+impl RegisterMap {
+    pub fn new() -> Self {
+        Self {
+            registers: HashMap::new(),
+            pointer: 0,
+            auto_increment: true,
+            write_counts: HashMap::new(),
+        }
+    }
+
+    /// Declare a register with its reset value and access type
+    pub fn with_register(mut self, register: u8, value: u8, access: RegisterAccess) -> Self {
+        self.registers.insert(register, Register { value, access });
+        self
+    }
+
+    /// Keep the pointer fixed on the addressed register during bursts
+    pub fn without_auto_increment(mut self) -> Self {
+        self.auto_increment = false;
+        self
+    }
+
+    /// Set a register value directly, bypassing its access type (e.g. new sensor data)
+    pub fn set_register(&mut self, register: u8, value: u8) {
+        self.registers
+            .entry(register)
+            .or_insert(Register {
+                value: 0,
+                access: RegisterAccess::ReadWrite,
+            })
+            .value = value;
+    }
+
+    /// Get a register value directly, without side effects such as clear-on-read
+    pub fn get_register(&self, register: u8) -> u8 {
+        self.registers.get(&register).map_or(0, |r| r.value)
+    }
+
+    /// Get the current register pointer
+    pub fn get_pointer(&self) -> u8 {
+        self.pointer
+    }
+
+    /// Get the number of bus writes that targeted a register, including ignored ones
+    pub fn get_write_count(&self, register: u8) -> usize {
+        self.write_counts.get(&register).copied().unwrap_or(0)
+    }
+
+    fn advance(&mut self) {
+        if self.auto_increment {
+            self.pointer = self.pointer.wrapping_add(1);
+        }
+    }
+}
+
+impl Default for RegisterMap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl I2cDevice for RegisterMap {
+    fn write(&mut self, data: &[u8]) -> I2cResult<()> {
+        let Some((&pointer, values)) = data.split_first() else {
+            return Ok(());
+        };
+
+        self.pointer = pointer;
+        for &value in values {
+            *self.write_counts.entry(self.pointer).or_insert(0) += 1;
+            let register = self.registers.entry(self.pointer).or_insert(Register {
+                value: 0,
+                access: RegisterAccess::ReadWrite,
+            });
+            if register.access != RegisterAccess::ReadOnly {
+                register.value = value;
+            }
+            self.advance();
+        }
+        Ok(())
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> I2cResult<()> {
+        for byte in buffer.iter_mut() {
+            *byte = match self.registers.get_mut(&self.pointer) {
+                Some(register) => match register.access {
+                    RegisterAccess::WriteOnly => 0,
+                    RegisterAccess::ClearOnRead => std::mem::take(&mut register.value),
+                    _ => register.value,
+                },
+                None => 0,
+            };
+            self.advance();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_register_map_access_types() {
+        let mut chip = RegisterMap::new()
+            .with_register(0xD0, 0x60, RegisterAccess::ReadOnly)
+            .with_register(0xE0, 0x00, RegisterAccess::WriteOnly)
+            .with_register(0xF3, 0x09, RegisterAccess::ClearOnRead);
+
+        chip.write(&[0xD0, 0xFF]).unwrap();
+        chip.write(&[0xE0, 0xB6]).unwrap();
+        assert_eq!(chip.get_register(0xD0), 0x60);
+        assert_eq!(chip.get_register(0xE0), 0xB6);
+        assert_eq!(chip.get_write_count(0xD0), 1);
+
+        let mut value = [0u8; 1];
+        chip.write(&[0xE0]).unwrap();
+        chip.read(&mut value).unwrap();
+        assert_eq!(value, [0x00]);
+
+        chip.write(&[0xF3]).unwrap();
+        chip.read(&mut value).unwrap();
+        assert_eq!(value, [0x09]);
+        chip.write(&[0xF3]).unwrap();
+        chip.read(&mut value).unwrap();
+        assert_eq!(value, [0x00]);
+    }
+
+    #[test]
+    fn test_register_map_pointer_increment() {
+        let mut chip = RegisterMap::new();
+        chip.write(&[0xFE, 0x11, 0x22, 0x33]).unwrap();
+        assert_eq!(chip.get_register(0x00), 0x33);
+        assert_eq!(chip.get_pointer(), 0x01);
+
+        let mut fixed = RegisterMap::new()
+            .with_register(0x10, 0xAA, RegisterAccess::ReadWrite)
+            .without_auto_increment();
+        let mut burst = [0u8; 3];
+        fixed.write(&[0x10]).unwrap();
+        fixed.read(&mut burst).unwrap();
+        assert_eq!(burst, [0xAA, 0xAA, 0xAA]);
+    }
+}
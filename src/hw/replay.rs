@@ -0,0 +1,448 @@
+//! Replay of recorded hardware sessions
+//!
+//! Record a session by wrapping the real backends in [`Traced`](super::Traced)
+//! and saving the trace with [`TraceRecorder::save`]. A [`ReplaySession`]
+//! loads the file and hands out GPIO, I2C and SPI backends, one per recorded
+//! source, that return the recorded results. Each source must repeat its
+//! recorded operations in order; the first call that diverges fails with the
+//! recorded and actual operation, and `done()` keeps reporting it. SPI mode
+//! and clock changes are not recorded and therefore not verified on replay.
+
+use super::expect::Expectations;
+use super::trace::{i2c_records, spi_request, TraceEntry, TraceOp, TraceRecorder};
+use super::{
+    expected_spi_reply, ChipSelect, Edge, EdgeEvent, Gpio, GpioEvents, I2c, I2cError, I2cOpRecord,
+    I2cOperation, I2cResult, PinMode, Pull, Spi, SpiConfig, SpiExpectation, SpiOperation,
+};
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+impl TraceRecorder {
+    /// Write the trace to a JSON file that `ReplaySession::load` can read
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        std::fs::write(path, self.to_json()?)
+            .with_context(|| format!("Failed to write recording {}", path.display()))
+    }
+}
+
+/// A recorded operation with its outcome
+#[derive(Clone)]
+struct ReplayStep {
+    op: TraceOp,
+    at_us: Option<u64>,
+    error: Option<String>,
+    i2c_error: Option<I2cError>,
+}
+
+impl ReplayStep {
+    fn failed(&self) -> bool {
+        self.error.is_some() || self.i2c_error.is_some()
+    }
+}
+
+impl fmt::Debug for ReplayStep {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.op)?;
+        if let Some(error) = &self.error {
+            write!(f, " failing with {:?}", error)?;
+        }
+        Ok(())
+    }
+}
+
+/// The recorded operations of one source, consumed in order
+type Queues = BTreeMap<String, Expectations<ReplayStep>>;
+
+/// A loaded recording, shared by the replay backends it hands out
+#[derive(Debug, Clone)]
+pub struct ReplaySession {
+    queues: Arc<Mutex<Queues>>,
+}
+
+fn bus_name(op: &TraceOp) -> &'static str {
+    match op {
+        TraceOp::GpioSetMode { .. }
+        | TraceOp::GpioSetPull { .. }
+        | TraceOp::GpioWrite { .. }
+        | TraceOp::GpioRead { .. }
+        | TraceOp::GpioEdge { .. } => "GPIO",
+        TraceOp::I2cWrite { .. } | TraceOp::I2cRead { .. } | TraceOp::I2cTransaction { .. } => {
+            "I2C"
+        }
+        TraceOp::SpiTransaction { .. } => "SPI",
+    }
+}
+
+impl ReplaySession {
+    pub fn new(entries: Vec<TraceEntry>) -> Self {
+        let mut steps: BTreeMap<String, (&'static str, Vec<ReplayStep>)> = BTreeMap::new();
+        for entry in entries {
+            steps
+                .entry(entry.source)
+                .or_insert_with(|| (bus_name(&entry.op), Vec::new()))
+                .1
+                .push(ReplayStep {
+                    op: entry.op,
+                    at_us: entry.at_us,
+                    error: entry.error,
+                    i2c_error: entry.i2c_error,
+                });
+        }
+
+        let queues = steps
+            .into_iter()
+            .map(|(source, (bus, steps))| (source, Expectations::new(bus, steps)))
+            .collect();
+        Self {
+            queues: Arc::new(Mutex::new(queues)),
+        }
+    }
+
+    /// Load a recording written by `TraceRecorder::save`
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read recording {}", path.display()))?;
+        let entries = serde_json::from_str(&json)
+            .with_context(|| format!("Invalid recording {}", path.display()))?;
+        Ok(Self::new(entries))
+    }
+
+    /// Replay the GPIO source recorded as `source`
+    pub fn gpio(&self, source: &str) -> ReplayGpio {
+        ReplayGpio(self.handle(source, "GPIO"))
+    }
+
+    /// Replay the I2C source recorded as `source`
+    pub fn i2c(&self, source: &str) -> ReplayI2c {
+        ReplayI2c(self.handle(source, "I2C"))
+    }
+
+    /// Replay the SPI source recorded as `source`
+    pub fn spi(&self, source: &str) -> ReplaySpi {
+        ReplaySpi {
+            handle: self.handle(source, "SPI"),
+            selected: None,
+        }
+    }
+
+    fn handle(&self, source: &str, bus: &'static str) -> Handle {
+        self.lock()
+            .entry(source.to_string())
+            .or_insert_with(|| Expectations::new(bus, Vec::new()));
+        Handle {
+            queues: self.queues.clone(),
+            source: source.to_string(),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Queues> {
+        self.queues
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Check that every source replayed its whole recording without diverging
+    pub fn done(&self) -> Result<()> {
+        for (source, queue) in self.lock().iter() {
+            queue
+                .done()
+                .map_err(|message| anyhow::anyhow!("{}: {}", source, message))?;
+        }
+        Ok(())
+    }
+}
+
+/// A replay backend's view of its source in the session
+#[derive(Debug, Clone)]
+struct Handle {
+    queues: Arc<Mutex<Queues>>,
+    source: String,
+}
+
+impl Handle {
+    /// Consume the next recorded step if `accept` takes it
+    fn next<R>(
+        &self,
+        actual: String,
+        accept: impl FnOnce(&ReplayStep) -> Option<R>,
+    ) -> std::result::Result<(R, ReplayStep), String> {
+        let mut queues = self
+            .queues
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        let queue = queues
+            .get_mut(&self.source)
+            .expect("replay handles are only created for registered sources");
+        queue
+            .next(actual, |step| {
+                accept(step).map(|reply| (reply, step.clone()))
+            })
+            .map_err(|message| format!("{}: {}", self.source, message))
+    }
+
+    fn next_is_edge(&self) -> bool {
+        let queues = self
+            .queues
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        queues
+            .get(&self.source)
+            .and_then(|queue| queue.peek())
+            .is_some_and(|step| matches!(step.op, TraceOp::GpioEdge { .. }))
+    }
+
+    /// Replay a GPIO or SPI step, failing the way the recording did
+    fn replay<R>(&self, actual: String, accept: impl FnOnce(&TraceOp) -> Option<R>) -> Result<R> {
+        let (reply, step) = self
+            .next(actual, |step| accept(&step.op))
+            .map_err(anyhow::Error::msg)?;
+        match step.error {
+            Some(error) => Err(anyhow::anyhow!("{}", error)),
+            None => Ok(reply),
+        }
+    }
+
+    /// Replay an I2C step, failing with the recorded typed error
+    ///
+    /// Failures recorded without one come back as `I2cError::Other`.
+    fn replay_i2c<R>(
+        &self,
+        actual: String,
+        accept: impl FnOnce(&ReplayStep) -> Option<R>,
+    ) -> I2cResult<R> {
+        let (reply, step) = self.next(actual, accept).map_err(I2cError::Other)?;
+        match (step.i2c_error, step.error) {
+            (Some(error), _) => Err(error),
+            (None, Some(message)) => Err(I2cError::Other(message)),
+            (None, None) => Ok(reply),
+        }
+    }
+}
+
+/// GPIO backend that replays a recorded source
+#[derive(Debug, Clone)]
+pub struct ReplayGpio(Handle);
+
+impl ReplayGpio {
+    fn replay_exact(&mut self, op: TraceOp) -> Result<()> {
+        self.0.replay(format!("{:?}", op), |recorded| {
+            (*recorded == op).then_some(())
+        })
+    }
+}
+
+impl Gpio for ReplayGpio {
+    fn write(&mut self, pin: u8, high: bool) -> Result<()> {
+        self.replay_exact(TraceOp::GpioWrite { pin, high })
+    }
+
+    fn read(&mut self, pin: u8) -> Result<bool> {
+        self.0.replay(
+            format!("GpioRead {{ pin: {} }}", pin),
+            |recorded| match *recorded {
+                TraceOp::GpioRead { pin: p, high } if p == pin => Some(high),
+                _ => None,
+            },
+        )
+    }
+
+    fn set_mode(&mut self, pin: u8, mode: PinMode) -> Result<()> {
+        self.replay_exact(TraceOp::GpioSetMode { pin, mode })
+    }
+
+    fn set_pull(&mut self, pin: u8, pull: Pull) -> Result<()> {
+        self.replay_exact(TraceOp::GpioSetPull { pin, pull })
+    }
+}
+
+impl GpioEvents for ReplayGpio {
+    fn subscribe(&mut self, _pin: u8, _edge: Edge) -> Result<()> {
+        Ok(())
+    }
+
+    fn unsubscribe(&mut self, _pin: u8) -> Result<()> {
+        Ok(())
+    }
+
+    /// Deliver the next recorded edge if it is next in the recording, else time out
+    fn wait_for_edge(&mut self, _timeout: Duration) -> Result<Option<EdgeEvent>> {
+        if !self.0.next_is_edge() {
+            return Ok(None);
+        }
+        let ((pin, edge), step) = self
+            .0
+            .next("GpioEdge".to_string(), |recorded| match recorded.op {
+                TraceOp::GpioEdge { pin, edge } => Some((pin, edge)),
+                _ => None,
+            })
+            .map_err(anyhow::Error::msg)?;
+        Ok(Some(EdgeEvent {
+            pin,
+            edge,
+            timestamp: Duration::from_micros(step.at_us.unwrap_or(0)),
+        }))
+    }
+}
+
+/// I2C backend that replays a recorded source
+#[derive(Debug, Clone)]
+pub struct ReplayI2c(Handle);
+
+impl I2c for ReplayI2c {
+    fn write(&mut self, address: u8, data: &[u8]) -> I2cResult<()> {
+        let op = TraceOp::I2cWrite {
+            address,
+            data: data.to_vec(),
+        };
+        self.0.replay_i2c(format!("{:?}", op), |recorded| {
+            (recorded.op == op).then_some(())
+        })
+    }
+
+    /// A recorded read only matches a buffer of the length it returned; failed
+    /// reads recorded no data and match any buffer.
+    fn read(&mut self, address: u8, buffer: &mut [u8]) -> I2cResult<usize> {
+        let actual = format!("I2cRead {{ address: {}, len: {} }}", address, buffer.len());
+        let data = self.0.replay_i2c(actual, |recorded| match &recorded.op {
+            TraceOp::I2cRead { address: a, data }
+                if *a == address && (recorded.failed() || data.len() == buffer.len()) =>
+            {
+                Some(data.clone())
+            }
+            _ => None,
+        })?;
+        buffer.copy_from_slice(&data);
+        Ok(data.len())
+    }
+
+    fn transaction(&mut self, address: u8, operations: &mut [I2cOperation<'_>]) -> I2cResult<()> {
+        let request = i2c_records(operations);
+        let actual = format!(
+            "I2cTransaction {{ address: {}, operations: {:?} }}",
+            address, request
+        );
+        let records = self.0.replay_i2c(actual, |recorded| match &recorded.op {
+            TraceOp::I2cTransaction {
+                address: a,
+                operations: steps,
+            } if *a == address
+                && steps.len() == request.len()
+                && steps.iter().zip(&request).all(|pair| match pair {
+                    (I2cOpRecord::Write(want), I2cOpRecord::Write(data)) => want == data,
+                    (I2cOpRecord::Read(reply), I2cOpRecord::Read(buffer)) => {
+                        reply.len() == buffer.len()
+                    }
+                    _ => false,
+                }) =>
+            {
+                Some(steps.clone())
+            }
+            _ => None,
+        })?;
+        for (operation, record) in operations.iter_mut().zip(&records) {
+            if let (I2cOperation::Read(buffer), I2cOpRecord::Read(reply)) = (operation, record) {
+                buffer.copy_from_slice(reply);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// SPI backend that replays a recorded source
+#[derive(Debug, Clone)]
+pub struct ReplaySpi {
+    handle: Handle,
+    selected: Option<ChipSelect>,
+}
+
+impl Spi for ReplaySpi {
+    fn transfer(&mut self, data: &mut [u8]) -> Result<()> {
+        self.transaction(&mut [SpiOperation::Transfer(data)])
+    }
+
+    fn transaction(&mut self, operations: &mut [SpiOperation<'_>]) -> Result<()> {
+        let selected = self.selected;
+        let actual = format!(
+            "SpiTransaction {{ target: {:?}, operations: {:?} }}",
+            selected,
+            spi_request(operations)
+        );
+        let reply = self.handle.replay(actual, |recorded| match recorded {
+            TraceOp::SpiTransaction {
+                target,
+                operations: steps,
+            } if *target == selected => {
+                let expected = SpiExpectation {
+                    target: *target,
+                    operations: steps.clone(),
+                };
+                expected_spi_reply(&expected, selected, operations)
+            }
+            _ => None,
+        })?;
+
+        let mut offset = 0;
+        for operation in operations.iter_mut() {
+            match operation {
+                SpiOperation::Write(data) => offset += data.len(),
+                SpiOperation::Read(buffer) | SpiOperation::Transfer(buffer) => {
+                    buffer.copy_from_slice(&reply[offset..offset + buffer.len()]);
+                    offset += buffer.len();
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Clock settings are not part of the recording, so a replay accepts any
+    /// configuration without checking it against the recorded session
+    fn configure(&mut self, _config: &SpiConfig) -> Result<()> {
+        Ok(())
+    }
+
+    fn select(&mut self, cs: ChipSelect) -> Result<()> {
+        self.selected = Some(cs);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hw::{MockI2c, RegisterAccess, RegisterMap, Traced};
+
+    #[test]
+    fn test_replay_restores_typed_i2c_errors() {
+        let trace = TraceRecorder::new();
+        let mut bus = Traced::new(MockI2c::new(), &trace, "i2c-1");
+        assert!(bus.write(0x48, &[]).is_err());
+
+        let session = ReplaySession::new(trace.entries());
+        let mut replay = session.i2c("i2c-1");
+        assert_eq!(replay.write(0x48, &[]), Err(I2cError::AddressNack(0x48)));
+        session.done().unwrap();
+    }
+
+    #[test]
+    fn test_replayed_read_needs_recorded_length() {
+        let trace = TraceRecorder::new();
+        let mut mock = MockI2c::new();
+        mock.attach_device(
+            0x48,
+            RegisterMap::new().with_register(0x00, 0x12, RegisterAccess::ReadOnly),
+        );
+        let mut bus = Traced::new(mock, &trace, "i2c-1");
+        bus.read(0x48, &mut [0u8; 2]).unwrap();
+
+        let session = ReplaySession::new(trace.entries());
+        let mut replay = session.i2c("i2c-1");
+        assert!(replay.read(0x48, &mut [0u8; 3]).is_err());
+        assert!(session.done().is_err());
+    }
+}
@@ -0,0 +1,284 @@
+//! I2C bus scanning and identification of common devices
+
+use super::{I2c, I2cError};
+
+/// First and last 7-bit addresses probed; the rest are reserved by the I2C spec
+pub const SCAN_FIRST_ADDRESS: u8 = 0x08;
+pub const SCAN_LAST_ADDRESS: u8 = 0x77;
+
+/// A register whose value identifies a chip
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChipId {
+    pub register: u8,
+    /// Values the register may hold for this chip
+    pub values: &'static [u8],
+}
+
+/// Entry in the table of devices the scanner can recognise
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KnownDevice {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub addresses: &'static [u8],
+    pub chip_id: Option<ChipId>,
+    /// Any byte written changes the device's state (e.g. a multiplexer's
+    /// channel mask), so nothing may be written to its addresses while probing
+    pub write_sensitive: bool,
+}
+
+/// Common parts found on Raspberry Pi HATs and breakout boards
+pub const KNOWN_DEVICES: &[KnownDevice] = &[
+    KnownDevice {
+        name: "BME280",
+        description: "Temperature, humidity and pressure sensor",
+        addresses: &[0x76, 0x77],
+        chip_id: Some(ChipId {
+            register: 0xD0,
+            values: &[0x60],
+        }),
+        write_sensitive: false,
+    },
+    KnownDevice {
+        name: "BMP280",
+        description: "Temperature and pressure sensor",
+        addresses: &[0x76, 0x77],
+        chip_id: Some(ChipId {
+            register: 0xD0,
+            values: &[0x56, 0x57, 0x58],
+        }),
+        write_sensitive: false,
+    },
+    KnownDevice {
+        name: "BME680",
+        description: "Gas, temperature, humidity and pressure sensor",
+        addresses: &[0x76, 0x77],
+        chip_id: Some(ChipId {
+            register: 0xD0,
+            values: &[0x61],
+        }),
+        write_sensitive: false,
+    },
+    KnownDevice {
+        name: "MPU-6050",
+        description: "6-axis accelerometer and gyroscope",
+        addresses: &[0x68, 0x69],
+        chip_id: Some(ChipId {
+            register: 0x75,
+            values: &[0x68],
+        }),
+        write_sensitive: false,
+    },
+    KnownDevice {
+        name: "VL53L0X",
+        description: "Time-of-flight distance sensor",
+        addresses: &[0x29],
+        chip_id: Some(ChipId {
+            register: 0xC0,
+            values: &[0xEE],
+        }),
+        write_sensitive: false,
+    },
+    KnownDevice {
+        name: "ADS1115",
+        description: "16-bit 4-channel ADC",
+        addresses: &[0x48, 0x49, 0x4A, 0x4B],
+        chip_id: None,
+        write_sensitive: false,
+    },
+    KnownDevice {
+        name: "MCP23017",
+        description: "16-bit I/O expander",
+        addresses: &[0x20, 0x21, 0x22, 0x23, 0x24, 0x25, 0x26, 0x27],
+        chip_id: None,
+        write_sensitive: false,
+    },
+    KnownDevice {
+        name: "PCF8574",
+        description: "8-bit I/O expander (often on LCD backpacks)",
+        addresses: &[0x20, 0x21, 0x22, 0x23, 0x24, 0x25, 0x26, 0x27],
+        chip_id: None,
+        write_sensitive: false,
+    },
+    KnownDevice {
+        name: "PCA9685",
+        description: "16-channel PWM / servo driver",
+        addresses: &[
+            0x40, 0x41, 0x42, 0x43, 0x44, 0x45, 0x46, 0x47, 0x48, 0x49, 0x4A, 0x4B, 0x4C, 0x4D,
+            0x4E, 0x4F,
+        ],
+        chip_id: None,
+        write_sensitive: false,
+    },
+    KnownDevice {
+        name: "SHT3x",
+        description: "Temperature and humidity sensor",
+        addresses: &[0x44, 0x45],
+        chip_id: None,
+        write_sensitive: false,
+    },
+    KnownDevice {
+        name: "SSD1306",
+        description: "128x64 OLED display controller",
+        addresses: &[0x3C, 0x3D],
+        chip_id: None,
+        write_sensitive: false,
+    },
+    KnownDevice {
+        name: "DS3231",
+        description: "Real-time clock",
+        addresses: &[0x68],
+        chip_id: None,
+        write_sensitive: false,
+    },
+    KnownDevice {
+        name: "TCA9548A",
+        description: "8-channel I2C multiplexer",
+        addresses: &[0x70, 0x71, 0x72, 0x73, 0x74, 0x75, 0x76, 0x77],
+        chip_id: None,
+        write_sensitive: true,
+    },
+    KnownDevice {
+        name: "HAT EEPROM",
+        description: "24C32 HAT identification EEPROM (on the ID_SD/ID_SC bus)",
+        addresses: &[0x50],
+        chip_id: None,
+        write_sensitive: false,
+    },
+];
+
+/// A known device that may be the one at a scanned address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Candidate {
+    pub device: &'static KnownDevice,
+    /// The device's chip-ID register matched; otherwise only the address did
+    pub confirmed: bool,
+}
+
+/// An address that acknowledged, with the devices it is likely to be
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScanResult {
+    pub address: u8,
+    /// Confirmed candidates first
+    pub candidates: Vec<Candidate>,
+}
+
+/// Probe one address the way `i2cdetect` does by default: a one-byte read in
+/// the EEPROM ranges (where a write could corrupt data), a quick write elsewhere
+pub fn probe<I: I2c + ?Sized>(i2c: &mut I, address: u8) -> bool {
+    let result = match address {
+        0x30..=0x37 | 0x50..=0x5F => i2c.read(address, &mut [0u8; 1]).map(|_| ()),
+        _ => i2c.write(address, &[]),
+    };
+
+    match result {
+        Ok(()) => true,
+        Err(I2cError::AddressNack(_)) | Err(I2cError::DataNack(_)) => false,
+        Err(e) => {
+            log::warn!("I2C probe of 0x{:02X} failed: {}", address, e);
+            false
+        }
+    }
+}
+
+/// Return every address in 0x08-0x77 that acknowledges
+pub fn scan_bus<I: I2c + ?Sized>(i2c: &mut I) -> Vec<u8> {
+    (SCAN_FIRST_ADDRESS..=SCAN_LAST_ADDRESS)
+        .filter(|&address| probe(i2c, address))
+        .collect()
+}
+
+/// Match a responding address against `KNOWN_DEVICES`
+///
+/// Devices with a chip-ID register are read to confirm them, and dropped if
+/// the ID does not match. Reading the ID writes a register pointer first, so
+/// it is skipped at addresses a write-sensitive part such as a TCA9548A may
+/// occupy; those candidates stay unconfirmed.
+pub fn identify<I: I2c + ?Sized>(i2c: &mut I, address: u8) -> Vec<Candidate> {
+    let write_sensitive = KNOWN_DEVICES
+        .iter()
+        .any(|device| device.write_sensitive && device.addresses.contains(&address));
+    let mut candidates: Vec<Candidate> = KNOWN_DEVICES
+        .iter()
+        .filter(|device| device.addresses.contains(&address))
+        .filter_map(|device| {
+            let Some(chip_id) = device.chip_id.filter(|_| !write_sensitive) else {
+                return Some(Candidate {
+                    device,
+                    confirmed: false,
+                });
+            };
+
+            let mut id = [0u8; 1];
+            match i2c.write_read(address, &[chip_id.register], &mut id) {
+                Ok(()) if chip_id.values.contains(&id[0]) => Some(Candidate {
+                    device,
+                    confirmed: true,
+                }),
+                _ => None,
+            }
+        })
+        .collect();
+
+    candidates.sort_by_key(|candidate| !candidate.confirmed);
+    candidates
+}
+
+/// Scan the bus and identify everything that responds
+pub fn scan_and_identify<I: I2c + ?Sized>(i2c: &mut I) -> Vec<ScanResult> {
+    scan_bus(i2c)
+        .into_iter()
+        .map(|address| ScanResult {
+            address,
+            candidates: identify(i2c, address),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hw::{MockI2c, RegisterAccess, RegisterMap};
+
+    #[test]
+    fn test_known_device_addresses_are_scannable() {
+        for device in KNOWN_DEVICES {
+            assert!(
+                device
+                    .addresses
+                    .iter()
+                    .all(|a| (SCAN_FIRST_ADDRESS..=SCAN_LAST_ADDRESS).contains(a)),
+                "{} lists an address outside the scan range",
+                device.name
+            );
+        }
+    }
+
+    #[test]
+    fn test_identify_uses_chip_id() {
+        let mut i2c = MockI2c::new();
+        i2c.attach_device(
+            0x68,
+            RegisterMap::new().with_register(0x75, 0x68, RegisterAccess::ReadOnly),
+        );
+
+        let candidates = identify(&mut i2c, 0x68);
+        let names: Vec<_> = candidates.iter().map(|c| c.device.name).collect();
+        assert_eq!(names, vec!["MPU-6050", "DS3231"]);
+        assert!(candidates[0].confirmed);
+        assert!(!candidates[1].confirmed);
+    }
+
+    #[test]
+    fn test_identify_never_writes_to_a_multiplexer() {
+        // Without auto-increment the pointer stands in for the channel mask,
+        // which any written byte would replace
+        let mut i2c = MockI2c::new();
+        let mux = i2c.attach_device(0x76, RegisterMap::new().without_auto_increment());
+
+        let candidates = identify(&mut i2c, 0x76);
+        assert_eq!(candidates.len(), 4);
+        assert!(candidates.iter().all(|c| !c.confirmed));
+        assert_eq!(mux.lock().unwrap().get_pointer(), 0);
+        assert!(i2c.get_transaction_log().is_empty());
+    }
+}
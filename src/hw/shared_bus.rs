@@ -0,0 +1,324 @@
+//! Sharing one I2C or SPI bus between threads
+//!
+//! [`SharedI2c`] and [`SharedSpi`] put a bus behind a mutex and hand out
+//! handles that implement the bus traits themselves, so each driver task gets
+//! its own handle and no hand-rolled `Arc<Mutex<_>>` wrappers. Every trait
+//! call holds the lock from start to finish, so a `transaction` or
+//! `write_read` is never interleaved with traffic from another handle. The
+//! bus counts how often handles had to wait for each other, and for how long.
+
+use super::spi_bus::{Binding, BusState};
+use super::{ChipSelect, Gpio, I2c, I2cOperation, I2cResult, Spi, SpiConfig, SpiOperation};
+use anyhow::Result;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex, MutexGuard, TryLockError};
+use std::time::{Duration, Instant};
+
+/// Lock contention on a shared bus
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BusMetrics {
+    /// Times a handle took the bus
+    pub acquisitions: usize,
+    /// Acquisitions that found the bus busy and had to wait
+    pub contended: usize,
+    /// Total time spent waiting for the bus
+    pub wait_time: Duration,
+    /// Longest single wait
+    pub max_wait: Duration,
+}
+
+/// Mutex that records how long callers wait for it
+#[derive(Debug)]
+struct MeteredLock<T> {
+    state: Mutex<T>,
+    metrics: Mutex<BusMetrics>,
+}
+
+impl<T> MeteredLock<T> {
+    fn new(state: T) -> Self {
+        Self {
+            state: Mutex::new(state),
+            metrics: Mutex::new(BusMetrics::default()),
+        }
+    }
+
+    /// Lock the state, waiting for other handles if need be
+    ///
+    /// A handle that panicked mid-transfer does not take the bus down with it.
+    fn acquire(&self) -> MutexGuard<'_, T> {
+        let (guard, waited) = match self.state.try_lock() {
+            Ok(guard) => (guard, None),
+            Err(TryLockError::Poisoned(poisoned)) => (poisoned.into_inner(), None),
+            Err(TryLockError::WouldBlock) => {
+                let start = Instant::now();
+                let guard = self
+                    .state
+                    .lock()
+                    .unwrap_or_else(|poisoned| poisoned.into_inner());
+                (guard, Some(start.elapsed()))
+            }
+        };
+
+        let mut metrics = self.metrics();
+        metrics.acquisitions += 1;
+        if let Some(waited) = waited {
+            metrics.contended += 1;
+            metrics.wait_time += waited;
+            metrics.max_wait = metrics.max_wait.max(waited);
+        }
+        guard
+    }
+
+    fn metrics(&self) -> MutexGuard<'_, BusMetrics> {
+        self.metrics
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+/// An I2C bus shared between threads; clones are handles to the same bus
+///
+/// Devices are addressed per call, so any handle can talk to any device.
+#[derive(Debug)]
+pub struct SharedI2c<I> {
+    lock: Arc<MeteredLock<I>>,
+}
+
+impl<I> Clone for SharedI2c<I> {
+    fn clone(&self) -> Self {
+        Self {
+            lock: self.lock.clone(),
+        }
+    }
+}
+
+impl<I: I2c> SharedI2c<I> {
+    pub fn new(i2c: I) -> Self {
+        Self {
+            lock: Arc::new(MeteredLock::new(i2c)),
+        }
+    }
+
+    /// Take the bus for a sequence of calls no other handle may interrupt,
+    /// or to inspect a mock
+    ///
+    /// Every handle blocks until the guard is dropped, so do not call through
+    /// one on the thread holding it.
+    pub fn controller(&self) -> MutexGuard<'_, I> {
+        self.lock.acquire()
+    }
+
+    pub fn get_metrics(&self) -> BusMetrics {
+        *self.lock.metrics()
+    }
+}
+
+impl<I: I2c> I2c for SharedI2c<I> {
+    fn write(&mut self, address: u8, data: &[u8]) -> I2cResult<()> {
+        self.lock.acquire().write(address, data)
+    }
+
+    fn read(&mut self, address: u8, buffer: &mut [u8]) -> I2cResult<usize> {
+        self.lock.acquire().read(address, buffer)
+    }
+
+    fn transaction(&mut self, address: u8, operations: &mut [I2cOperation<'_>]) -> I2cResult<()> {
+        self.lock.acquire().transaction(address, operations)
+    }
+
+    fn write_read(&mut self, address: u8, data: &[u8], buffer: &mut [u8]) -> I2cResult<()> {
+        self.lock.acquire().write_read(address, data, buffer)
+    }
+}
+
+type SpiState<S> = BusState<S, Arc<Mutex<dyn Gpio + Send>>>;
+
+/// The controller inside a locked [`SharedSpi`]
+struct Controller<'a, S>(MutexGuard<'a, SpiState<S>>);
+
+impl<S> Deref for Controller<'_, S> {
+    type Target = S;
+
+    fn deref(&self) -> &S {
+        &self.0.spi
+    }
+}
+
+impl<S> DerefMut for Controller<'_, S> {
+    fn deref_mut(&mut self) -> &mut S {
+        &mut self.0.spi
+    }
+}
+
+/// An SPI controller shared between threads
+///
+/// The thread-safe counterpart of [`SpiBus`](super::SpiBus): each device is
+/// reached through a [`SharedSpiDevice`] handle bound to its chip select and
+/// settings, and chip selects are asserted only while the bus is locked.
+pub struct SharedSpi<S> {
+    lock: Arc<MeteredLock<SpiState<S>>>,
+}
+
+// Written by hand: the GPIO behind chip selects is a trait object, and locking
+// the bus here could block on a handle mid-transfer
+impl<S> fmt::Debug for SharedSpi<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedSpi")
+            .field("metrics", &*self.lock.metrics())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S> Clone for SharedSpi<S> {
+    fn clone(&self) -> Self {
+        Self {
+            lock: self.lock.clone(),
+        }
+    }
+}
+
+impl<S: Spi> SharedSpi<S> {
+    pub fn new(spi: S) -> Self {
+        Self {
+            lock: Arc::new(MeteredLock::new(BusState::new(spi))),
+        }
+    }
+
+    /// Drive `ChipSelect::Gpio` lines through `gpio`
+    pub fn with_cs_gpio<G: Gpio + Send + 'static>(self, gpio: Arc<Mutex<G>>) -> Self {
+        self.lock.acquire().cs_gpio = Some(gpio);
+        self
+    }
+
+    /// Claim a chip-select line and get a handle for the device behind it
+    ///
+    /// A GPIO chip select is made an output and deasserted (high) straight
+    /// away, so the device ignores traffic meant for the others.
+    pub fn device(&self, cs: ChipSelect, config: SpiConfig) -> Result<SharedSpiDevice<S>> {
+        self.lock.acquire().claim(cs)?;
+        Ok(SharedSpiDevice {
+            lock: self.lock.clone(),
+            device: Binding { cs, config },
+        })
+    }
+
+    /// Take the controller, e.g. to inspect a mock
+    ///
+    /// Device handles block until the guard is dropped.
+    pub fn controller(&self) -> impl DerefMut<Target = S> + '_ {
+        Controller(self.lock.acquire())
+    }
+
+    pub fn get_metrics(&self) -> BusMetrics {
+        *self.lock.metrics()
+    }
+}
+
+/// Handle for one device on a [`SharedSpi`]
+///
+/// Clones share the chip-select line, which is released when the last one is
+/// dropped; each clone keeps its own settings.
+pub struct SharedSpiDevice<S> {
+    lock: Arc<MeteredLock<SpiState<S>>>,
+    device: Binding,
+}
+
+impl<S> fmt::Debug for SharedSpiDevice<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SharedSpiDevice")
+            .field("cs", &self.device.cs)
+            .field("config", &self.device.config)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<S> SharedSpiDevice<S> {
+    pub fn chip_select(&self) -> ChipSelect {
+        self.device.cs
+    }
+
+    pub fn config(&self) -> SpiConfig {
+        self.device.config
+    }
+
+    /// Lock the bus state without counting it as an acquisition
+    fn state(&self) -> MutexGuard<'_, SpiState<S>> {
+        self.lock
+            .state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl<S: Spi> Spi for SharedSpiDevice<S> {
+    fn transfer(&mut self, data: &mut [u8]) -> Result<()> {
+        self.lock
+            .acquire()
+            .with_selected(&self.device, |spi| spi.transfer(data))
+    }
+
+    fn transaction(&mut self, operations: &mut [SpiOperation<'_>]) -> Result<()> {
+        self.lock
+            .acquire()
+            .with_selected(&self.device, |spi| spi.transaction(operations))
+    }
+
+    /// Change this handle's settings; they are applied on its next transfer
+    fn configure(&mut self, config: &SpiConfig) -> Result<()> {
+        self.device.config = *config;
+        Ok(())
+    }
+
+    fn select(&mut self, cs: ChipSelect) -> Result<()> {
+        self.device.select(cs)
+    }
+}
+
+impl<S> Clone for SharedSpiDevice<S> {
+    fn clone(&self) -> Self {
+        self.state().retain(self.device.cs);
+        Self {
+            lock: self.lock.clone(),
+            device: self.device,
+        }
+    }
+}
+
+impl<S> Drop for SharedSpiDevice<S> {
+    fn drop(&mut self) {
+        self.state().release(self.device.cs);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hw::MockSpi;
+
+    #[test]
+    fn test_chip_select_released_by_last_clone() {
+        let bus = SharedSpi::new(MockSpi::new());
+        let device = bus
+            .device(ChipSelect::Hardware(0), SpiConfig::default())
+            .unwrap();
+        let clone = device.clone();
+        drop(device);
+        assert!(bus
+            .device(ChipSelect::Hardware(0), SpiConfig::default())
+            .is_err());
+
+        drop(clone);
+        assert!(bus
+            .device(ChipSelect::Hardware(0), SpiConfig::default())
+            .is_ok());
+        assert!(bus
+            .device(ChipSelect::Gpio(8), SpiConfig::default())
+            .is_err());
+        assert_eq!(bus.get_metrics().contended, 0);
+        let device = bus
+            .device(ChipSelect::Hardware(1), SpiConfig::default())
+            .unwrap();
+        assert!(format!("{:?}", device).contains("Hardware(1)"));
+    }
+}
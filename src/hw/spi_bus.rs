@@ -0,0 +1,299 @@
+//! Sharing one SPI controller between several devices
+
+use super::{ChipSelect, Gpio, PinMode, Spi, SpiConfig, SpiOperation};
+use anyhow::Result;
+use std::cell::{RefCell, RefMut};
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+
+/// Shared access to the GPIO controller that drives chip-select lines
+pub(crate) trait CsGpio {
+    fn with_gpio(&self, f: impl FnOnce(&mut dyn Gpio) -> Result<()>) -> Result<()>;
+}
+
+impl CsGpio for Rc<RefCell<dyn Gpio>> {
+    fn with_gpio(&self, f: impl FnOnce(&mut dyn Gpio) -> Result<()>) -> Result<()> {
+        f(&mut *self.borrow_mut())
+    }
+}
+
+impl CsGpio for Arc<Mutex<dyn Gpio + Send>> {
+    fn with_gpio(&self, f: impl FnOnce(&mut dyn Gpio) -> Result<()>) -> Result<()> {
+        f(&mut *self.lock().unwrap_or_else(|poisoned| poisoned.into_inner()))
+    }
+}
+
+/// Chip-select bookkeeping and device switching for a shared controller
+///
+/// [`SpiBus`] keeps this in a `RefCell` and
+/// [`SharedSpi`](super::SharedSpi) behind a mutex; everything else about
+/// sharing the controller lives here.
+pub(crate) struct BusState<S, G> {
+    pub(crate) spi: S,
+    pub(crate) cs_gpio: Option<G>,
+    /// Handles holding each chip-select line
+    claimed: HashMap<ChipSelect, usize>,
+    /// Device the controller was last set up for
+    active: Option<(ChipSelect, SpiConfig)>,
+}
+
+impl<S, G> BusState<S, G> {
+    pub(crate) fn new(spi: S) -> Self {
+        Self {
+            spi,
+            cs_gpio: None,
+            claimed: HashMap::new(),
+            active: None,
+        }
+    }
+
+    /// Add a handle to an already claimed chip-select line
+    pub(crate) fn retain(&mut self, cs: ChipSelect) {
+        *self.claimed.entry(cs).or_default() += 1;
+    }
+
+    /// Drop a handle, freeing the line once no handle holds it
+    pub(crate) fn release(&mut self, cs: ChipSelect) {
+        if let Some(handles) = self.claimed.get_mut(&cs) {
+            *handles -= 1;
+            if *handles == 0 {
+                self.claimed.remove(&cs);
+            }
+        }
+    }
+}
+
+impl<S: Spi, G: CsGpio> BusState<S, G> {
+    /// Claim a chip-select line for a new device handle
+    ///
+    /// A GPIO chip select is made an output and deasserted (high) straight
+    /// away, so the device ignores traffic meant for the others.
+    pub(crate) fn claim(&mut self, cs: ChipSelect) -> Result<()> {
+        if self.claimed.contains_key(&cs) {
+            return Err(anyhow::anyhow!("Chip select {} is already in use", cs));
+        }
+
+        if let ChipSelect::Gpio(pin) = cs {
+            let gpio = self.cs_gpio.as_ref().ok_or_else(|| {
+                anyhow::anyhow!(
+                    "Chip select {} needs a bus created with a GPIO controller",
+                    cs
+                )
+            })?;
+            gpio.with_gpio(|gpio| {
+                gpio.set_mode(pin, PinMode::Output)?;
+                gpio.write(pin, true)
+            })?;
+        }
+
+        self.claimed.insert(cs, 1);
+        Ok(())
+    }
+
+    /// Run `f` on the controller with `device` configured and selected
+    pub(crate) fn with_selected(
+        &mut self,
+        device: &Binding,
+        f: impl FnOnce(&mut S) -> Result<()>,
+    ) -> Result<()> {
+        self.activate(device.cs, &device.config)?;
+        self.set_cs(device.cs, true)?;
+        let result = f(&mut self.spi);
+        let released = self.set_cs(device.cs, false);
+        result.and(released)
+    }
+
+    /// Configure and select the controller for a device, unless it already is
+    fn activate(&mut self, cs: ChipSelect, config: &SpiConfig) -> Result<()> {
+        if self.active == Some((cs, *config)) {
+            return Ok(());
+        }
+        self.active = None;
+        self.spi.configure(config)?;
+        self.spi.select(cs)?;
+        self.active = Some((cs, *config));
+        Ok(())
+    }
+
+    /// Drive a GPIO chip select; hardware lines are handled by the controller
+    fn set_cs(&mut self, cs: ChipSelect, asserted: bool) -> Result<()> {
+        let ChipSelect::Gpio(pin) = cs else {
+            return Ok(());
+        };
+        let gpio = self
+            .cs_gpio
+            .as_ref()
+            .ok_or_else(|| anyhow::anyhow!("No GPIO controller for chip select {}", cs))?;
+        gpio.with_gpio(|gpio| gpio.write(pin, !asserted))
+    }
+}
+
+/// The chip select and settings a device handle is bound to
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Binding {
+    pub(crate) cs: ChipSelect,
+    pub(crate) config: SpiConfig,
+}
+
+impl Binding {
+    /// Check a [`Spi::select`] call against the bound line
+    pub(crate) fn select(&self, cs: ChipSelect) -> Result<()> {
+        if cs != self.cs {
+            return Err(anyhow::anyhow!(
+                "SPI device handle is bound to {}, not {}",
+                self.cs,
+                cs
+            ));
+        }
+        Ok(())
+    }
+}
+
+type LocalState<S> = BusState<S, Rc<RefCell<dyn Gpio>>>;
+
+/// An SPI controller shared by the devices on its bus
+///
+/// Each device is reached through an [`SpiDevice`] handle that implements
+/// [`Spi`] itself, so drivers written against the trait work unchanged. Before
+/// every transfer the handle applies its own mode and speed and selects its
+/// chip-select line. GPIO chip selects are active low and driven through a
+/// [`Gpio`] that the bus shares with the rest of the application.
+pub struct SpiBus<S> {
+    state: Rc<RefCell<LocalState<S>>>,
+}
+
+impl<S: Spi> SpiBus<S> {
+    pub fn new(spi: S) -> Self {
+        Self {
+            state: Rc::new(RefCell::new(BusState::new(spi))),
+        }
+    }
+
+    /// Drive `ChipSelect::Gpio` lines through `gpio`
+    pub fn with_cs_gpio<G: Gpio + 'static>(self, gpio: Rc<RefCell<G>>) -> Self {
+        self.state.borrow_mut().cs_gpio = Some(gpio);
+        self
+    }
+
+    /// Claim a chip-select line and get a handle for the device behind it
+    ///
+    /// A GPIO chip select is made an output and deasserted (high) straight
+    /// away, so the device ignores traffic meant for the others.
+    pub fn device(&self, cs: ChipSelect, config: SpiConfig) -> Result<SpiDevice<S>> {
+        self.state.borrow_mut().claim(cs)?;
+        Ok(SpiDevice {
+            bus: self.state.clone(),
+            device: Binding { cs, config },
+        })
+    }
+
+    /// Borrow the underlying controller, e.g. to inspect a mock
+    ///
+    /// Panics if called while a device handle is mid-transfer.
+    pub fn controller(&self) -> RefMut<'_, S> {
+        RefMut::map(self.state.borrow_mut(), |state| &mut state.spi)
+    }
+}
+
+/// Handle for one device on an [`SpiBus`]
+///
+/// Dropping the handle releases its chip-select line.
+pub struct SpiDevice<S> {
+    bus: Rc<RefCell<LocalState<S>>>,
+    device: Binding,
+}
+
+impl<S> SpiDevice<S> {
+    pub fn chip_select(&self) -> ChipSelect {
+        self.device.cs
+    }
+
+    pub fn config(&self) -> SpiConfig {
+        self.device.config
+    }
+}
+
+impl<S: Spi> Spi for SpiDevice<S> {
+    fn transfer(&mut self, data: &mut [u8]) -> Result<()> {
+        self.bus
+            .borrow_mut()
+            .with_selected(&self.device, |spi| spi.transfer(data))
+    }
+
+    fn transaction(&mut self, operations: &mut [SpiOperation<'_>]) -> Result<()> {
+        self.bus
+            .borrow_mut()
+            .with_selected(&self.device, |spi| spi.transaction(operations))
+    }
+
+    /// Change this device's settings; they are applied on its next transfer
+    fn configure(&mut self, config: &SpiConfig) -> Result<()> {
+        self.device.config = *config;
+        Ok(())
+    }
+
+    fn select(&mut self, cs: ChipSelect) -> Result<()> {
+        self.device.select(cs)
+    }
+}
+
+impl<S> Drop for SpiDevice<S> {
+    fn drop(&mut self) {
+        self.bus.borrow_mut().release(self.device.cs);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hw::{MockGpio, MockSpi, SpiMode};
+
+    #[test]
+    fn test_chip_select_is_claimed_once() {
+        let bus = SpiBus::new(MockSpi::new());
+        let device = bus
+            .device(ChipSelect::Hardware(0), SpiConfig::default())
+            .unwrap();
+        assert!(bus
+            .device(ChipSelect::Hardware(0), SpiConfig::default())
+            .is_err());
+
+        drop(device);
+        assert!(bus
+            .device(ChipSelect::Hardware(0), SpiConfig::default())
+            .is_ok());
+        assert!(bus
+            .device(ChipSelect::Gpio(8), SpiConfig::default())
+            .is_err());
+    }
+
+    #[test]
+    fn test_device_settings_applied_on_switch() {
+        let gpio = Rc::new(RefCell::new(MockGpio::new()));
+        let bus = SpiBus::new(MockSpi::new()).with_cs_gpio(gpio.clone());
+        let mut adc = bus
+            .device(
+                ChipSelect::Hardware(1),
+                SpiConfig::new(SpiMode::Mode1, 500_000),
+            )
+            .unwrap();
+        let mut display = bus
+            .device(ChipSelect::Gpio(25), SpiConfig::default())
+            .unwrap();
+        assert!(gpio.borrow_mut().read(25).unwrap());
+
+        adc.transfer(&mut [0x01, 0x80, 0x00]).unwrap();
+        assert_eq!(
+            bus.controller().get_config(),
+            SpiConfig::new(SpiMode::Mode1, 500_000)
+        );
+
+        display.transfer(&mut [0xAF]).unwrap();
+        assert_eq!(bus.controller().get_config(), SpiConfig::default());
+        assert_eq!(bus.controller().get_selected(), Some(ChipSelect::Gpio(25)));
+        assert_eq!(gpio.borrow().get_write_count(25), 3);
+        assert!(gpio.borrow_mut().read(25).unwrap());
+        assert!(display.select(ChipSelect::Hardware(1)).is_err());
+    }
+}
@@ -0,0 +1,97 @@
+//! Ready-made `SpiResponder`s for modelling chips on a `MockSpi`
+
+use super::SpiResponder;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Chip that answers according to the command opcode in the first byte
+///
+/// The reply to a known opcode is clocked back on the bytes after it, as on
+/// SPI flash and most sensors. The opcode byte itself, unknown opcodes and
+/// anything past the end of a reply read as zero.
+#[derive(Debug, Clone, Default)]
+pub struct CommandResponder {
+    replies: HashMap<u8, Vec<u8>>,
+    command_counts: HashMap<u8, usize>,
+}
+
+impl CommandResponder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Declare the reply to an opcode
+    pub fn with_reply(mut self, opcode: u8, reply: Vec<u8>) -> Self {
+        self.set_reply(opcode, reply);
+        self
+    }
+
+    /// Change the reply to an opcode (e.g. a status register that changed)
+    pub fn set_reply(&mut self, opcode: u8, reply: Vec<u8>) {
+        self.replies.insert(opcode, reply);
+    }
+
+    /// Get the number of transactions that started with `opcode`
+    pub fn get_command_count(&self, opcode: u8) -> usize {
+        self.command_counts.get(&opcode).copied().unwrap_or(0)
+    }
+}
+
+impl SpiResponder for CommandResponder {
+    fn respond(&mut self, sent: &[u8]) -> Vec<u8> {
+        let Some(&opcode) = sent.first() else {
+            return Vec::new();
+        };
+        *self.command_counts.entry(opcode).or_insert(0) += 1;
+
+        let mut reply = vec![0];
+        if let Some(data) = self.replies.get(&opcode) {
+            reply.extend_from_slice(data);
+        }
+        reply
+    }
+}
+
+/// Responder backed by a closure, created with [`from_fn`]
+pub struct FnResponder<F>(F);
+
+/// Compute replies with a closure from the bytes sent
+pub fn from_fn<F>(f: F) -> FnResponder<F>
+where
+    F: FnMut(&[u8]) -> Vec<u8> + Send,
+{
+    FnResponder(f)
+}
+
+impl<F> fmt::Debug for FnResponder<F> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("FnResponder")
+    }
+}
+
+impl<F> SpiResponder for FnResponder<F>
+where
+    F: FnMut(&[u8]) -> Vec<u8> + Send,
+{
+    fn respond(&mut self, sent: &[u8]) -> Vec<u8> {
+        (self.0)(sent)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_command_responder_replies_after_opcode() {
+        let mut flash = CommandResponder::new().with_reply(0x9F, vec![0xEF, 0x40, 0x18]);
+        assert_eq!(
+            flash.respond(&[0x9F, 0, 0, 0]),
+            vec![0x00, 0xEF, 0x40, 0x18]
+        );
+        assert_eq!(flash.respond(&[0x05, 0]), vec![0x00]);
+        assert_eq!(flash.respond(&[]), Vec::<u8>::new());
+        assert_eq!(flash.get_command_count(0x9F), 1);
+        assert_eq!(flash.get_command_count(0x05), 1);
+    }
+}
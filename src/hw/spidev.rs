@@ -0,0 +1,496 @@
+//! Linux spidev backend (`/dev/spidevB.C`)
+
+use super::{BitOrder, ChipSelect, Spi, SpiConfig, SpiMode, SpiOperation};
+use anyhow::{Context, Result};
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::mem::size_of;
+use std::os::fd::AsRawFd;
+use std::path::Path;
+
+const fn ioc(dir: u64, nr: u64, size: usize) -> u64 {
+    (dir << 30) | ((size as u64) << 16) | ((b'k' as u64) << 8) | nr
+}
+
+const IOC_WRITE: u64 = 1;
+
+pub(crate) const SPI_IOC_WR_MODE: u64 = ioc(IOC_WRITE, 1, size_of::<u8>());
+pub(crate) const SPI_IOC_WR_LSB_FIRST: u64 = ioc(IOC_WRITE, 2, size_of::<u8>());
+pub(crate) const SPI_IOC_WR_BITS_PER_WORD: u64 = ioc(IOC_WRITE, 3, size_of::<u8>());
+pub(crate) const SPI_IOC_WR_MAX_SPEED_HZ: u64 = ioc(IOC_WRITE, 4, size_of::<u32>());
+
+/// Mode flag that keeps the controller's own chip select idle
+pub(crate) const SPI_NO_CS: u8 = 0x40;
+
+/// `SPI_IOC_MESSAGE(n)`
+pub(crate) const fn spi_ioc_message(n: usize) -> u64 {
+    let size = n * size_of::<SpiIocTransfer>();
+    ioc(IOC_WRITE, 0, if size < (1 << 14) { size } else { 0 })
+}
+
+/// `struct spi_ioc_transfer`
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct SpiIocTransfer {
+    pub tx_buf: u64,
+    pub rx_buf: u64,
+    pub len: u32,
+    pub speed_hz: u32,
+    pub delay_usecs: u16,
+    pub bits_per_word: u8,
+    pub cs_change: u8,
+    pub tx_nbits: u8,
+    pub rx_nbits: u8,
+    pub word_delay_usecs: u8,
+    pub pad: u8,
+}
+
+const _: () = assert!(size_of::<SpiIocTransfer>() == 32);
+
+/// Build the `spi_ioc_transfer` array for a message
+///
+/// The returned transfers borrow the operation buffers through raw addresses and
+/// must not outlive `operations`.
+pub(crate) fn build_transfers(
+    operations: &mut [SpiOperation<'_>],
+    speed_hz: u32,
+    bits_per_word: u8,
+) -> io::Result<Vec<SpiIocTransfer>> {
+    operations
+        .iter_mut()
+        .map(|operation| {
+            let (tx_buf, rx_buf, len) = match operation {
+                SpiOperation::Write(data) => (data.as_ptr() as u64, 0, data.len()),
+                SpiOperation::Read(buffer) => (0, buffer.as_mut_ptr() as u64, buffer.len()),
+                SpiOperation::Transfer(data) => {
+                    let ptr = data.as_mut_ptr() as u64;
+                    (ptr, ptr, data.len())
+                }
+            };
+            let len = u32::try_from(len).map_err(|_| {
+                io::Error::new(io::ErrorKind::InvalidInput, "SPI operation too long")
+            })?;
+            Ok(SpiIocTransfer {
+                tx_buf,
+                rx_buf,
+                len,
+                speed_hz,
+                bits_per_word,
+                ..Default::default()
+            })
+        })
+        .collect()
+}
+
+/// The ioctls on a spidev node, behind a seam so tests can substitute a fake device
+pub(crate) trait DeviceIo {
+    fn set_mode(&mut self, dev: &File, mode: u8) -> io::Result<()>;
+    fn set_lsb_first(&mut self, dev: &File, lsb_first: bool) -> io::Result<()>;
+    fn set_bits_per_word(&mut self, dev: &File, bits: u8) -> io::Result<()>;
+    fn set_max_speed_hz(&mut self, dev: &File, hz: u32) -> io::Result<()>;
+    /// Clock all operations as one `SPI_IOC_MESSAGE`
+    fn message(
+        &mut self,
+        dev: &File,
+        operations: &mut [SpiOperation<'_>],
+        speed_hz: u32,
+        bits_per_word: u8,
+    ) -> io::Result<()>;
+}
+
+/// `DeviceIo` backed by real ioctls on the character device
+struct SysDeviceIo;
+
+fn ioctl<T>(dev: &File, request: u64, arg: *mut T) -> io::Result<()> {
+    // SAFETY: callers pass a pointer to a live value of the size encoded in `request`
+    let ret = unsafe { libc::ioctl(dev.as_raw_fd(), request as _, arg) };
+    if ret < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+impl DeviceIo for SysDeviceIo {
+    fn set_mode(&mut self, dev: &File, mut mode: u8) -> io::Result<()> {
+        ioctl(dev, SPI_IOC_WR_MODE, &mut mode)
+    }
+
+    fn set_lsb_first(&mut self, dev: &File, lsb_first: bool) -> io::Result<()> {
+        ioctl(dev, SPI_IOC_WR_LSB_FIRST, &mut u8::from(lsb_first))
+    }
+
+    fn set_bits_per_word(&mut self, dev: &File, mut bits: u8) -> io::Result<()> {
+        ioctl(dev, SPI_IOC_WR_BITS_PER_WORD, &mut bits)
+    }
+
+    fn set_max_speed_hz(&mut self, dev: &File, mut hz: u32) -> io::Result<()> {
+        ioctl(dev, SPI_IOC_WR_MAX_SPEED_HZ, &mut hz)
+    }
+
+    fn message(
+        &mut self,
+        dev: &File,
+        operations: &mut [SpiOperation<'_>],
+        speed_hz: u32,
+        bits_per_word: u8,
+    ) -> io::Result<()> {
+        let mut transfers = build_transfers(operations, speed_hz, bits_per_word)?;
+        if spi_ioc_message(transfers.len()) == spi_ioc_message(0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "too many operations for one SPI message",
+            ));
+        }
+        // SAFETY: each transfer points into an operation buffer borrowed for the call
+        ioctl(
+            dev,
+            spi_ioc_message(transfers.len()),
+            transfers.as_mut_ptr(),
+        )
+    }
+}
+
+/// `Spi` implementation on a Linux spidev node
+pub struct Spidev {
+    dev: File,
+    io: Box<dyn DeviceIo>,
+    /// Native chip select the node is bound to, when known from its name
+    chip_select: Option<u8>,
+    /// A GPIO chip select is in use, so `SPI_NO_CS` is set
+    no_cs: bool,
+    mode: u8,
+    bit_order: BitOrder,
+    bits_per_word: u8,
+    max_speed_hz: u32,
+}
+
+impl Spidev {
+    /// Default clock for newly opened devices
+    pub const DEFAULT_SPEED_HZ: u32 = 1_000_000;
+
+    /// Open a spidev node such as `/dev/spidev0.0`
+    ///
+    /// The device is put into mode 0, MSB first, 8 bits per word at
+    /// [`Self::DEFAULT_SPEED_HZ`].
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let dev = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .with_context(|| format!("Failed to open SPI device {}", path.display()))?;
+        let mut spidev = Self::with_io(dev, Box::new(SysDeviceIo))?;
+        spidev.chip_select = parse_chip_select(path);
+        Ok(spidev)
+    }
+
+    /// Open `/dev/spidev<bus>.<chip_select>`
+    pub fn open_device(bus: u8, chip_select: u8) -> Result<Self> {
+        Self::open(format!("/dev/spidev{}.{}", bus, chip_select))
+    }
+
+    pub(crate) fn with_io(dev: File, io: Box<dyn DeviceIo>) -> Result<Self> {
+        let mut spidev = Self {
+            dev,
+            io,
+            chip_select: None,
+            no_cs: false,
+            mode: 0,
+            bit_order: BitOrder::MsbFirst,
+            bits_per_word: 8,
+            max_speed_hz: Self::DEFAULT_SPEED_HZ,
+        };
+        spidev.configure(&SpiConfig::new(SpiMode::Mode0, Self::DEFAULT_SPEED_HZ))?;
+        Ok(spidev)
+    }
+
+    /// Set the SPI mode (0-3): bit 1 is CPOL, bit 0 is CPHA
+    pub fn set_mode(&mut self, mode: u8) -> Result<()> {
+        if mode > 3 {
+            return Err(anyhow::anyhow!("Invalid SPI mode {}", mode));
+        }
+        let flags = if self.no_cs { SPI_NO_CS } else { 0 };
+        self.io
+            .set_mode(&self.dev, mode | flags)
+            .with_context(|| format!("Failed to set SPI mode {}", mode))?;
+        self.mode = mode;
+        Ok(())
+    }
+
+    pub fn set_bit_order(&mut self, bit_order: BitOrder) -> Result<()> {
+        self.io
+            .set_lsb_first(&self.dev, bit_order == BitOrder::LsbFirst)
+            .with_context(|| format!("Failed to set SPI bit order {:?}", bit_order))?;
+        self.bit_order = bit_order;
+        Ok(())
+    }
+
+    pub fn set_bits_per_word(&mut self, bits: u8) -> Result<()> {
+        self.io
+            .set_bits_per_word(&self.dev, bits)
+            .with_context(|| format!("Failed to set {} bits per word", bits))?;
+        self.bits_per_word = bits;
+        Ok(())
+    }
+
+    pub fn set_max_speed_hz(&mut self, hz: u32) -> Result<()> {
+        self.io
+            .set_max_speed_hz(&self.dev, hz)
+            .with_context(|| format!("Failed to set SPI speed to {} Hz", hz))?;
+        self.max_speed_hz = hz;
+        Ok(())
+    }
+
+    /// Native chip select of the node, parsed from a `spidevB.C` path
+    pub fn chip_select(&self) -> Option<u8> {
+        self.chip_select
+    }
+
+    pub fn mode(&self) -> u8 {
+        self.mode
+    }
+
+    pub fn bit_order(&self) -> BitOrder {
+        self.bit_order
+    }
+
+    /// Current settings as an `SpiConfig`
+    pub fn config(&self) -> SpiConfig {
+        SpiConfig::new(
+            SpiMode::from_number(self.mode).unwrap_or_default(),
+            self.max_speed_hz,
+        )
+        .with_bit_order(self.bit_order)
+        .with_bits_per_word(self.bits_per_word)
+    }
+
+    pub fn bits_per_word(&self) -> u8 {
+        self.bits_per_word
+    }
+
+    pub fn max_speed_hz(&self) -> u32 {
+        self.max_speed_hz
+    }
+}
+
+impl Spi for Spidev {
+    fn transfer(&mut self, data: &mut [u8]) -> Result<()> {
+        self.transaction(&mut [SpiOperation::Transfer(data)])
+    }
+
+    fn transaction(&mut self, operations: &mut [SpiOperation<'_>]) -> Result<()> {
+        self.io
+            .message(&self.dev, operations, self.max_speed_hz, self.bits_per_word)
+            .context("SPI transfer failed")
+    }
+
+    fn configure(&mut self, config: &SpiConfig) -> Result<()> {
+        self.set_mode(config.mode.number())?;
+        self.set_bit_order(config.bit_order)?;
+        self.set_bits_per_word(config.bits_per_word)?;
+        self.set_max_speed_hz(config.speed_hz)
+    }
+
+    /// Each spidev node drives a single native chip select, so hardware lines
+    /// other than its own need their own node; GPIO chip selects set `SPI_NO_CS`
+    fn select(&mut self, cs: ChipSelect) -> Result<()> {
+        let no_cs = match cs {
+            ChipSelect::Hardware(line) => match self.chip_select {
+                Some(own) if own != line => {
+                    return Err(anyhow::anyhow!(
+                        "spidev node is bound to CE{}; open its CE{} node to reach {}",
+                        own,
+                        line,
+                        cs
+                    ))
+                }
+                _ => false,
+            },
+            ChipSelect::Gpio(_) => true,
+        };
+        if no_cs != self.no_cs {
+            self.no_cs = no_cs;
+            self.set_mode(self.mode)?;
+        }
+        Ok(())
+    }
+}
+
+/// Chip select from a node name such as `spidev0.1`
+fn parse_chip_select(path: &Path) -> Option<u8> {
+    let name = path.file_name()?.to_str()?.strip_prefix("spidev")?;
+    let (_, cs) = name.split_once('.')?;
+    cs.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::prelude::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Loopback device: MISO is wired to MOSI with every bit inverted
+    #[derive(Default)]
+    struct FakeDeviceState {
+        mode: u8,
+        lsb_first: bool,
+        bits_per_word: u8,
+        speed_hz: u32,
+        messages: Vec<Vec<Vec<u8>>>,
+    }
+
+    struct FakeDevice(Rc<RefCell<FakeDeviceState>>);
+
+    impl DeviceIo for FakeDevice {
+        fn set_mode(&mut self, _dev: &File, mode: u8) -> io::Result<()> {
+            self.0.borrow_mut().mode = mode;
+            Ok(())
+        }
+
+        fn set_lsb_first(&mut self, _dev: &File, lsb_first: bool) -> io::Result<()> {
+            self.0.borrow_mut().lsb_first = lsb_first;
+            Ok(())
+        }
+
+        fn set_bits_per_word(&mut self, _dev: &File, bits: u8) -> io::Result<()> {
+            self.0.borrow_mut().bits_per_word = bits;
+            Ok(())
+        }
+
+        fn set_max_speed_hz(&mut self, _dev: &File, hz: u32) -> io::Result<()> {
+            self.0.borrow_mut().speed_hz = hz;
+            Ok(())
+        }
+
+        fn message(
+            &mut self,
+            _dev: &File,
+            operations: &mut [SpiOperation<'_>],
+            _speed_hz: u32,
+            _bits_per_word: u8,
+        ) -> io::Result<()> {
+            let mut sent = Vec::new();
+            for operation in operations {
+                match operation {
+                    SpiOperation::Write(data) => sent.push(data.to_vec()),
+                    SpiOperation::Read(buffer) => {
+                        sent.push(vec![0; buffer.len()]);
+                        buffer.fill(0xFF);
+                    }
+                    SpiOperation::Transfer(data) => {
+                        sent.push(data.to_vec());
+                        data.iter_mut().for_each(|b| *b = !*b);
+                    }
+                }
+            }
+            self.0.borrow_mut().messages.push(sent);
+            Ok(())
+        }
+    }
+
+    fn fake_device() -> (
+        Spidev,
+        Rc<RefCell<FakeDeviceState>>,
+        assert_fs::NamedTempFile,
+    ) {
+        let node = assert_fs::NamedTempFile::new("spidev0.0").unwrap();
+        node.touch().unwrap();
+        let state = Rc::new(RefCell::new(FakeDeviceState::default()));
+        let spi = Spidev::with_io(
+            File::open(node.path()).unwrap(),
+            Box::new(FakeDevice(state.clone())),
+        )
+        .unwrap();
+        (spi, state, node)
+    }
+
+    #[test]
+    fn test_ioctl_numbers_match_kernel_headers() {
+        assert_eq!(SPI_IOC_WR_MODE, 0x4001_6B01);
+        assert_eq!(SPI_IOC_WR_LSB_FIRST, 0x4001_6B02);
+        assert_eq!(SPI_IOC_WR_BITS_PER_WORD, 0x4001_6B03);
+        assert_eq!(SPI_IOC_WR_MAX_SPEED_HZ, 0x4004_6B04);
+        assert_eq!(spi_ioc_message(1), 0x4020_6B00);
+        assert_eq!(spi_ioc_message(3), 0x4060_6B00);
+    }
+
+    #[test]
+    fn test_build_transfers_buffers() {
+        let command = [0x03, 0x00, 0x10, 0x00];
+        let mut buffer = [0u8; 16];
+        let mut operations = [
+            SpiOperation::Write(&command),
+            SpiOperation::Read(&mut buffer),
+        ];
+        let transfers = build_transfers(&mut operations, 500_000, 8).unwrap();
+
+        assert_eq!(transfers.len(), 2);
+        assert_eq!(transfers[0].tx_buf, command.as_ptr() as u64);
+        assert_eq!((transfers[0].rx_buf, transfers[0].len), (0, 4));
+        assert_eq!((transfers[1].tx_buf, transfers[1].len), (0, 16));
+        assert!(transfers
+            .iter()
+            .all(|t| t.speed_hz == 500_000 && t.bits_per_word == 8 && t.cs_change == 0));
+    }
+
+    #[test]
+    fn test_spidev_configuration_and_transfer() {
+        let (mut spi, state, _node) = fake_device();
+        assert_eq!(state.borrow().speed_hz, Spidev::DEFAULT_SPEED_HZ);
+
+        spi.set_mode(3).unwrap();
+        spi.set_max_speed_hz(8_000_000).unwrap();
+        assert!(spi.set_mode(4).is_err());
+        assert_eq!(spi.mode(), 3);
+        assert_eq!(
+            (state.borrow().mode, state.borrow().speed_hz),
+            (3, 8_000_000)
+        );
+
+        let mut data = [0x0F, 0xF0];
+        spi.transfer(&mut data).unwrap();
+        assert_eq!(data, [0xF0, 0x0F]);
+
+        let mut reply = [0u8; 2];
+        spi.write_read(&[0x9F], &mut reply).unwrap();
+        assert_eq!(reply, [0xFF, 0xFF]);
+        assert_eq!(state.borrow().messages[1], vec![vec![0x9F], vec![0, 0]]);
+    }
+
+    #[test]
+    fn test_spidev_chip_select_routing() {
+        assert_eq!(parse_chip_select(Path::new("/dev/spidev0.1")), Some(1));
+        assert_eq!(parse_chip_select(Path::new("/tmp/spi")), None);
+
+        let (mut spi, state, _node) = fake_device();
+        spi.chip_select = Some(0);
+        spi.configure(&SpiConfig::new(SpiMode::Mode2, 250_000))
+            .unwrap();
+        assert_eq!((state.borrow().mode, state.borrow().speed_hz), (2, 250_000));
+
+        spi.select(ChipSelect::Hardware(0)).unwrap();
+        assert!(spi.select(ChipSelect::Hardware(1)).is_err());
+        spi.select(ChipSelect::Gpio(8)).unwrap();
+        assert_eq!(state.borrow().mode, 2 | SPI_NO_CS);
+        assert_eq!(spi.mode(), 2);
+        spi.select(ChipSelect::Hardware(0)).unwrap();
+        assert_eq!(state.borrow().mode, 2);
+    }
+
+    #[test]
+    fn test_spidev_applies_full_config() {
+        let (mut spi, state, _node) = fake_device();
+        let config = SpiConfig::new(SpiMode::Mode1, 2_000_000)
+            .with_bit_order(BitOrder::LsbFirst)
+            .with_bits_per_word(9);
+        spi.configure(&config).unwrap();
+
+        let fake = state.borrow();
+        assert_eq!(
+            (fake.mode, fake.lsb_first, fake.bits_per_word, fake.speed_hz),
+            (1, true, 9, 2_000_000)
+        );
+        assert_eq!(spi.config(), config);
+    }
+}
@@ -0,0 +1,263 @@
+//! Legacy sysfs GPIO backend (`/sys/class/gpio`)
+
+use super::{Gpio, PinMode, Pull};
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+/// Default location of the sysfs GPIO interface
+pub const SYSFS_GPIO_PATH: &str = "/sys/class/gpio";
+
+/// How long to wait for the kernel (and udev) to create `gpioN` after export
+const EXPORT_POLL_ATTEMPTS: u32 = 10;
+const EXPORT_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// `Gpio` implementation on the sysfs interface
+///
+/// Pins are exported on first use and unexported again when dropped, unless
+/// they were already exported by someone else.
+#[derive(Debug)]
+pub struct SysfsGpio {
+    base: PathBuf,
+    modes: HashMap<u8, PinMode>,
+    exported_by_us: Vec<u8>,
+}
+
+impl SysfsGpio {
+    pub fn new() -> Self {
+        Self::with_base_path(SYSFS_GPIO_PATH)
+    }
+
+    /// Root the backend somewhere other than `/sys/class/gpio` (e.g. a fake tree in tests)
+    pub fn with_base_path(base: impl Into<PathBuf>) -> Self {
+        Self {
+            base: base.into(),
+            modes: HashMap::new(),
+            exported_by_us: Vec::new(),
+        }
+    }
+
+    /// Get the directory this backend operates in
+    pub fn base_path(&self) -> &Path {
+        &self.base
+    }
+
+    /// Whether `gpioN` is currently present under the base path
+    pub fn is_exported(&self, pin: u8) -> bool {
+        self.pin_dir(pin).is_dir()
+    }
+
+    /// Export a pin, waiting for its `gpioN` directory to appear
+    pub fn export(&mut self, pin: u8) -> Result<()> {
+        if self.is_exported(pin) {
+            return Ok(());
+        }
+
+        fs::write(self.base.join("export"), pin.to_string())
+            .with_context(|| format!("Failed to export GPIO {}", pin))?;
+
+        for _ in 0..EXPORT_POLL_ATTEMPTS {
+            if self.is_exported(pin) {
+                self.exported_by_us.push(pin);
+                return Ok(());
+            }
+            thread::sleep(EXPORT_POLL_INTERVAL);
+        }
+
+        Err(anyhow::anyhow!(
+            "GPIO {} did not appear at {} after export",
+            pin,
+            self.pin_dir(pin).display()
+        ))
+    }
+
+    /// Unexport a pin, releasing it back to the kernel
+    pub fn unexport(&mut self, pin: u8) -> Result<()> {
+        if !self.is_exported(pin) {
+            return Err(anyhow::anyhow!("GPIO {} is not exported", pin));
+        }
+
+        fs::write(self.base.join("unexport"), pin.to_string())
+            .with_context(|| format!("Failed to unexport GPIO {}", pin))?;
+        self.modes.remove(&pin);
+        self.exported_by_us.retain(|&p| p != pin);
+        Ok(())
+    }
+
+    fn pin_dir(&self, pin: u8) -> PathBuf {
+        self.base.join(format!("gpio{}", pin))
+    }
+
+    fn write_attr(&self, pin: u8, attr: &str, value: &str) -> Result<()> {
+        let path = self.pin_dir(pin).join(attr);
+        fs::write(&path, value)
+            .with_context(|| format!("Failed to write '{}' to {}", value, path.display()))
+    }
+
+    fn read_attr(&self, pin: u8, attr: &str) -> Result<String> {
+        let path = self.pin_dir(pin).join(attr);
+        let value = fs::read_to_string(&path)
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        Ok(value.trim().to_string())
+    }
+
+    /// Export the pin if needed and learn its current direction
+    fn ensure_exported(&mut self, pin: u8) -> Result<PinMode> {
+        if let Some(mode) = self.modes.get(&pin) {
+            return Ok(*mode);
+        }
+
+        self.export(pin)?;
+        let mode = match self.read_attr(pin, "direction")?.as_str() {
+            "out" => PinMode::Output,
+            _ => PinMode::Input,
+        };
+        self.modes.insert(pin, mode);
+        Ok(mode)
+    }
+}
+
+impl Default for SysfsGpio {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for SysfsGpio {
+    fn drop(&mut self) {
+        for pin in std::mem::take(&mut self.exported_by_us) {
+            if let Err(e) = fs::write(self.base.join("unexport"), pin.to_string()) {
+                log::warn!("Failed to unexport GPIO {} on drop: {}", pin, e);
+            }
+        }
+    }
+}
+
+impl Gpio for SysfsGpio {
+    /// Pins nobody configured keep the direction sysfs reports; an input is
+    /// never switched to output behind the caller's back.
+    fn write(&mut self, pin: u8, high: bool) -> Result<()> {
+        match self.ensure_exported(pin)? {
+            PinMode::Output => self.write_attr(pin, "value", if high { "1" } else { "0" }),
+            _ => Err(anyhow::anyhow!("Cannot write to input pin {}", pin)),
+        }
+    }
+
+    fn read(&mut self, pin: u8) -> Result<bool> {
+        self.ensure_exported(pin)?;
+        match self.read_attr(pin, "value")?.as_str() {
+            "0" => Ok(false),
+            "1" => Ok(true),
+            other => Err(anyhow::anyhow!(
+                "Unexpected value '{}' for GPIO {}",
+                other,
+                pin
+            )),
+        }
+    }
+
+    fn set_mode(&mut self, pin: u8, mode: PinMode) -> Result<()> {
+        let direction = match mode {
+            PinMode::Input => "in",
+            PinMode::Output => "out",
+            PinMode::Alt(func) => {
+                return Err(anyhow::anyhow!(
+                    "ALT{} is not supported by sysfs GPIO",
+                    func
+                ));
+            }
+        };
+
+        self.export(pin)?;
+        self.write_attr(pin, "direction", direction)?;
+        self.modes.insert(pin, mode);
+        Ok(())
+    }
+
+    /// sysfs has no bias control, so only `Pull::None` is accepted.
+    fn set_pull(&mut self, pin: u8, pull: Pull) -> Result<()> {
+        match pull {
+            Pull::None => Ok(()),
+            _ => Err(anyhow::anyhow!(
+                "Pull {:?} on GPIO {} is not supported by sysfs GPIO",
+                pull,
+                pin
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assert_fs::prelude::*;
+    use assert_fs::TempDir;
+
+    /// Lay out a fake sysfs tree with `pins` already present, as the kernel
+    /// would after exporting them.
+    fn fake_sysfs(pins: &[(u8, &str, &str)]) -> TempDir {
+        let root = TempDir::new().unwrap();
+        root.child("export").touch().unwrap();
+        root.child("unexport").touch().unwrap();
+        for (pin, direction, value) in pins {
+            let dir = root.child(format!("gpio{}", pin));
+            dir.child("direction").write_str(direction).unwrap();
+            dir.child("value").write_str(value).unwrap();
+        }
+        root
+    }
+
+    #[test]
+    fn test_sysfs_direction_and_value() {
+        let root = fake_sysfs(&[(17, "in\n", "1\n"), (18, "in\n", "0\n")]);
+        let mut gpio = SysfsGpio::with_base_path(root.path());
+
+        assert!(gpio.read(17).unwrap());
+        assert!(gpio.write(17, true).is_err());
+
+        gpio.set_mode(18, PinMode::Output).unwrap();
+        root.child("gpio18/direction").assert("out");
+        gpio.write(18, true).unwrap();
+        root.child("gpio18/value").assert("1");
+        assert!(gpio.read(18).unwrap());
+
+        assert!(gpio.set_mode(18, PinMode::Alt(2)).is_err());
+        assert!(gpio.set_pull(18, Pull::Up).is_err());
+        assert!(gpio.set_pull(18, Pull::None).is_ok());
+    }
+
+    #[test]
+    fn test_sysfs_unconfigured_write_respects_direction() {
+        let root = fake_sysfs(&[(23, "in\n", "0\n"), (24, "out\n", "0\n")]);
+        let mut gpio = SysfsGpio::with_base_path(root.path());
+
+        assert!(gpio.write(23, true).is_err());
+        root.child("gpio23/direction").assert("in\n");
+        root.child("gpio23/value").assert("0\n");
+
+        gpio.write(24, true).unwrap();
+        root.child("gpio24/direction").assert("out\n");
+        root.child("gpio24/value").assert("1");
+    }
+
+    #[test]
+    fn test_sysfs_export_and_unexport() {
+        let root = fake_sysfs(&[(5, "in", "0")]);
+        let mut gpio = SysfsGpio::with_base_path(root.path());
+
+        // Already present: no write to `export`
+        gpio.export(5).unwrap();
+        root.child("export").assert("");
+
+        // Nothing creates gpio6 in the fake tree, so export times out
+        assert!(gpio.export(6).is_err());
+        root.child("export").assert("6");
+
+        gpio.unexport(5).unwrap();
+        root.child("unexport").assert("5");
+        assert!(gpio.unexport(7).is_err());
+    }
+}
@@ -0,0 +1,125 @@
+//! Simulated operation latency for mocks
+//!
+//! Without timing the mocks complete every operation instantly. Given a
+//! [`BusTiming`] and a `VirtualClock`, a mock advances the clock by the time
+//! each operation would take on a real bus (a fixed overhead plus the bits
+//! clocked out) and keeps a running total, so slow drivers show up in tests
+//! without slowing the tests down.
+
+use super::clock::VirtualClock;
+use std::time::Duration;
+
+/// How long operations on a bus take
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BusTiming {
+    /// Fixed cost of every operation, e.g. the syscall and driver setup
+    pub overhead: Duration,
+    /// Bus clock; `None` uses the bus's own configured speed (SPI), or makes
+    /// data free on buses without one
+    pub clock_hz: Option<u32>,
+    /// Clock cycles per byte, including any acknowledge bit
+    pub bits_per_byte: u32,
+}
+
+impl BusTiming {
+    /// Only a fixed cost per operation, as for GPIO
+    pub fn fixed(overhead: Duration) -> Self {
+        Self {
+            overhead,
+            clock_hz: None,
+            bits_per_byte: 0,
+        }
+    }
+
+    /// I2C at `clock_hz`, with nine clocks per byte and one address byte per segment
+    pub fn i2c(clock_hz: u32) -> Self {
+        Self {
+            overhead: Duration::ZERO,
+            clock_hz: Some(clock_hz),
+            bits_per_byte: 9,
+        }
+    }
+
+    /// SPI at the speed set with `Spi::configure`
+    pub fn spi() -> Self {
+        Self {
+            overhead: Duration::ZERO,
+            clock_hz: None,
+            bits_per_byte: 8,
+        }
+    }
+
+    pub fn with_overhead(mut self, overhead: Duration) -> Self {
+        self.overhead = overhead;
+        self
+    }
+
+    /// Clock the bus at `clock_hz` regardless of its configuration
+    pub fn with_clock(mut self, clock_hz: u32) -> Self {
+        self.clock_hz = Some(clock_hz);
+        self
+    }
+
+    /// Time to move `bytes` in one operation; `bus_hz` is the bus's configured speed
+    pub fn duration(&self, bytes: usize, bus_hz: Option<u32>) -> Duration {
+        let bits = bytes as u64 * u64::from(self.bits_per_byte);
+        let data = match self.clock_hz.or(bus_hz) {
+            Some(hz) if hz > 0 && bits > 0 => {
+                Duration::from_nanos((bits * 1_000_000_000).div_ceil(u64::from(hz)))
+            }
+            _ => Duration::ZERO,
+        };
+        self.overhead + data
+    }
+}
+
+/// Latency a mock charges to a virtual clock, with the running total
+#[derive(Debug, Clone)]
+pub(crate) struct Latency {
+    clock: VirtualClock,
+    timing: BusTiming,
+    total: Duration,
+}
+
+impl Latency {
+    pub(crate) fn new(clock: &VirtualClock, timing: BusTiming) -> Self {
+        Self {
+            clock: clock.clone(),
+            timing,
+            total: Duration::ZERO,
+        }
+    }
+
+    /// Advance the clock by the time an operation moving `bytes` takes
+    pub(crate) fn charge(&mut self, bytes: usize, bus_hz: Option<u32>) {
+        let duration = self.timing.duration(bytes, bus_hz);
+        self.clock.advance(duration);
+        self.total += duration;
+    }
+
+    pub(crate) fn total(&self) -> Duration {
+        self.total
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bus_timing_durations() {
+        // Address byte plus two data bytes at 100 kHz: 27 clocks
+        let i2c = BusTiming::i2c(100_000).with_overhead(Duration::from_micros(50));
+        assert_eq!(i2c.duration(3, None), Duration::from_micros(320));
+
+        let spi = BusTiming::spi();
+        assert_eq!(spi.duration(4, Some(1_000_000)), Duration::from_micros(32));
+        assert_eq!(
+            spi.with_clock(8_000_000).duration(4, Some(1_000_000)),
+            Duration::from_micros(4)
+        );
+
+        let gpio = BusTiming::fixed(Duration::from_micros(2));
+        assert_eq!(gpio.duration(0, None), Duration::from_micros(2));
+    }
+}
@@ -0,0 +1,451 @@
+//! Shared, timestamped trace of operations across GPIO, I2C and SPI
+//!
+//! A [`TraceRecorder`] is a cheap cloneable handle. Mocks append to it once
+//! given one with `set_trace`, and any real backend can be wrapped in
+//! [`Traced`] to do the same, so a single trace shows how a driver interleaved
+//! its operations on every bus.
+
+use super::clock::{Clock, SystemClock};
+use super::{
+    ChipSelect, Edge, EdgeEvent, Gpio, GpioEvents, I2c, I2cError, I2cOpRecord, I2cOperation,
+    I2cResult, PinMode, Pull, Spi, SpiConfig, SpiOpRecord, SpiOperation,
+};
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// A traced operation and the data it moved
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum TraceOp {
+    GpioSetMode {
+        pin: u8,
+        mode: PinMode,
+    },
+    GpioSetPull {
+        pin: u8,
+        pull: Pull,
+    },
+    GpioWrite {
+        pin: u8,
+        high: bool,
+    },
+    GpioRead {
+        pin: u8,
+        high: bool,
+    },
+    /// An edge event delivered to the driver
+    GpioEdge {
+        pin: u8,
+        edge: Edge,
+    },
+    I2cWrite {
+        address: u8,
+        data: Vec<u8>,
+    },
+    I2cRead {
+        address: u8,
+        data: Vec<u8>,
+    },
+    I2cTransaction {
+        address: u8,
+        operations: Vec<I2cOpRecord>,
+    },
+    /// One chip-select assertion
+    SpiTransaction {
+        target: Option<ChipSelect>,
+        operations: Vec<SpiOpRecord>,
+    },
+}
+
+/// One line of a trace
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TraceEntry {
+    /// Position in the trace, across all sources
+    pub seq: usize,
+    /// Microseconds since the recorder was created; `None` in untimed copies
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub at_us: Option<u64>,
+    /// Name the mock or backend was given when attached
+    pub source: String,
+    #[serde(flatten)]
+    pub op: TraceOp,
+    /// Set if the operation failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// The typed error of a failed I2C operation, so a replay fails the same way
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub i2c_error: Option<I2cError>,
+}
+
+#[derive(Debug)]
+struct TraceLog {
+    clock: Arc<dyn Clock>,
+    entries: Vec<TraceEntry>,
+}
+
+/// Shared recorder that mocks and traced backends append to
+///
+/// Clones append to and read from the same trace.
+#[derive(Debug, Clone)]
+pub struct TraceRecorder {
+    log: Arc<Mutex<TraceLog>>,
+}
+
+impl TraceRecorder {
+    pub fn new() -> Self {
+        Self::with_clock(SystemClock::new())
+    }
+
+    /// Timestamp entries with `clock`, e.g. the `VirtualClock` the mocks run on
+    pub fn with_clock(clock: impl Clock + 'static) -> Self {
+        Self {
+            log: Arc::new(Mutex::new(TraceLog {
+                clock: Arc::new(clock),
+                entries: Vec::new(),
+            })),
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, TraceLog> {
+        self.log
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    /// Append an operation; `error` marks it as failed
+    pub fn record(&self, source: &str, op: TraceOp, error: Option<String>) {
+        self.append(source, op, error, None);
+    }
+
+    fn append(
+        &self,
+        source: &str,
+        op: TraceOp,
+        error: Option<String>,
+        i2c_error: Option<I2cError>,
+    ) {
+        let mut log = self.lock();
+        let at_us = log.clock.now().as_micros() as u64;
+        let seq = log.entries.len();
+        log.entries.push(TraceEntry {
+            seq,
+            at_us: Some(at_us),
+            source: source.to_string(),
+            op,
+            error,
+            i2c_error,
+        });
+    }
+
+    pub fn entries(&self) -> Vec<TraceEntry> {
+        self.lock().entries.clone()
+    }
+
+    /// Get the entries without timestamps, for stable snapshots
+    pub fn untimed(&self) -> Vec<TraceEntry> {
+        let mut entries = self.entries();
+        for entry in &mut entries {
+            entry.at_us = None;
+        }
+        entries
+    }
+
+    /// Get the entries recorded by one source
+    pub fn entries_from(&self, source: &str) -> Vec<TraceEntry> {
+        self.lock()
+            .entries
+            .iter()
+            .filter(|entry| entry.source == source)
+            .cloned()
+            .collect()
+    }
+
+    /// Get the operations in order, without metadata
+    pub fn ops(&self) -> Vec<TraceOp> {
+        self.lock()
+            .entries
+            .iter()
+            .map(|entry| entry.op.clone())
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn clear(&self) {
+        self.lock().entries.clear();
+    }
+
+    /// Serialize the trace as pretty-printed JSON
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(&self.lock().entries)
+    }
+}
+
+impl Default for TraceRecorder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A recorder together with the source name to record under
+#[derive(Debug, Clone)]
+pub(crate) struct Tap {
+    recorder: TraceRecorder,
+    source: String,
+}
+
+impl Tap {
+    pub(crate) fn new(recorder: &TraceRecorder, source: &str) -> Self {
+        Self {
+            recorder: recorder.clone(),
+            source: source.to_string(),
+        }
+    }
+
+    pub(crate) fn record<T, E: Display>(&self, op: TraceOp, result: &std::result::Result<T, E>) {
+        let error = result.as_ref().err().map(|e| e.to_string());
+        self.recorder.record(&self.source, op, error);
+    }
+
+    /// Record an I2C operation, keeping the typed error if it failed
+    pub(crate) fn record_i2c<T>(&self, op: TraceOp, result: &I2cResult<T>) {
+        let error = result.as_ref().err();
+        self.recorder.append(
+            &self.source,
+            op,
+            error.map(|e| e.to_string()),
+            error.cloned(),
+        );
+    }
+
+    /// Record something the hardware reported rather than a call that could fail
+    pub(crate) fn record_event(&self, op: TraceOp) {
+        self.recorder.record(&self.source, op, None);
+    }
+}
+
+/// Records of a finished I2C transaction, with the bytes that were read
+pub(crate) fn i2c_records(operations: &[I2cOperation<'_>]) -> Vec<I2cOpRecord> {
+    operations
+        .iter()
+        .map(|operation| match operation {
+            I2cOperation::Write(data) => I2cOpRecord::Write(data.to_vec()),
+            I2cOperation::Read(buffer) => I2cOpRecord::Read(buffer.to_vec()),
+        })
+        .collect()
+}
+
+/// Capture what an SPI transaction sends, before it overwrites transfer buffers
+pub(crate) fn spi_request(operations: &[SpiOperation<'_>]) -> Vec<SpiOpRecord> {
+    operations
+        .iter()
+        .map(|operation| match operation {
+            SpiOperation::Write(data) => SpiOpRecord::Write(data.to_vec()),
+            SpiOperation::Read(_) => SpiOpRecord::Read(Vec::new()),
+            SpiOperation::Transfer(data) => SpiOpRecord::Transfer {
+                sent: data.to_vec(),
+                received: Vec::new(),
+            },
+        })
+        .collect()
+}
+
+/// Fill captured SPI records with what the transaction received
+pub(crate) fn spi_complete(records: &mut [SpiOpRecord], operations: &[SpiOperation<'_>]) {
+    for (record, operation) in records.iter_mut().zip(operations) {
+        match (record, operation) {
+            (SpiOpRecord::Read(data), SpiOperation::Read(buffer)) => *data = buffer.to_vec(),
+            (SpiOpRecord::Transfer { received, .. }, SpiOperation::Transfer(data)) => {
+                *received = data.to_vec()
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Wrapper that traces every operation of a GPIO, I2C or SPI backend
+pub struct Traced<T> {
+    inner: T,
+    tap: Tap,
+    selected: Option<ChipSelect>,
+}
+
+impl<T> Traced<T> {
+    pub fn new(inner: T, recorder: &TraceRecorder, source: &str) -> Self {
+        Self {
+            inner,
+            tap: Tap::new(recorder, source),
+            selected: None,
+        }
+    }
+
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn inner_mut(&mut self) -> &mut T {
+        &mut self.inner
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<T: Gpio> Gpio for Traced<T> {
+    fn write(&mut self, pin: u8, high: bool) -> Result<()> {
+        let result = self.inner.write(pin, high);
+        self.tap.record(TraceOp::GpioWrite { pin, high }, &result);
+        result
+    }
+
+    fn read(&mut self, pin: u8) -> Result<bool> {
+        let result = self.inner.read(pin);
+        let high = *result.as_ref().unwrap_or(&false);
+        self.tap.record(TraceOp::GpioRead { pin, high }, &result);
+        result
+    }
+
+    fn set_mode(&mut self, pin: u8, mode: PinMode) -> Result<()> {
+        let result = self.inner.set_mode(pin, mode);
+        self.tap.record(TraceOp::GpioSetMode { pin, mode }, &result);
+        result
+    }
+
+    fn set_pull(&mut self, pin: u8, pull: Pull) -> Result<()> {
+        let result = self.inner.set_pull(pin, pull);
+        self.tap.record(TraceOp::GpioSetPull { pin, pull }, &result);
+        result
+    }
+}
+
+impl<T: GpioEvents> GpioEvents for Traced<T> {
+    fn subscribe(&mut self, pin: u8, edge: Edge) -> Result<()> {
+        self.inner.subscribe(pin, edge)
+    }
+
+    fn unsubscribe(&mut self, pin: u8) -> Result<()> {
+        self.inner.unsubscribe(pin)
+    }
+
+    fn wait_for_edge(&mut self, timeout: Duration) -> Result<Option<EdgeEvent>> {
+        let result = self.inner.wait_for_edge(timeout);
+        if let Ok(Some(event)) = &result {
+            self.tap.record_event(TraceOp::GpioEdge {
+                pin: event.pin,
+                edge: event.edge,
+            });
+        }
+        result
+    }
+}
+
+impl<T: I2c> I2c for Traced<T> {
+    fn write(&mut self, address: u8, data: &[u8]) -> I2cResult<()> {
+        let result = self.inner.write(address, data);
+        let op = TraceOp::I2cWrite {
+            address,
+            data: data.to_vec(),
+        };
+        self.tap.record_i2c(op, &result);
+        result
+    }
+
+    fn read(&mut self, address: u8, buffer: &mut [u8]) -> I2cResult<usize> {
+        let result = self.inner.read(address, buffer);
+        let len = *result.as_ref().unwrap_or(&0);
+        let op = TraceOp::I2cRead {
+            address,
+            data: buffer[..len].to_vec(),
+        };
+        self.tap.record_i2c(op, &result);
+        result
+    }
+
+    fn transaction(&mut self, address: u8, operations: &mut [I2cOperation<'_>]) -> I2cResult<()> {
+        let result = self.inner.transaction(address, operations);
+        let op = TraceOp::I2cTransaction {
+            address,
+            operations: i2c_records(operations),
+        };
+        self.tap.record_i2c(op, &result);
+        result
+    }
+}
+
+impl<T: Spi> Spi for Traced<T> {
+    fn transfer(&mut self, data: &mut [u8]) -> Result<()> {
+        self.transaction(&mut [SpiOperation::Transfer(data)])
+    }
+
+    fn transaction(&mut self, operations: &mut [SpiOperation<'_>]) -> Result<()> {
+        let mut records = spi_request(operations);
+        let result = self.inner.transaction(operations);
+        spi_complete(&mut records, operations);
+        let op = TraceOp::SpiTransaction {
+            target: self.selected,
+            operations: records,
+        };
+        self.tap.record(op, &result);
+        result
+    }
+
+    fn configure(&mut self, config: &SpiConfig) -> Result<()> {
+        self.inner.configure(config)
+    }
+
+    fn select(&mut self, cs: ChipSelect) -> Result<()> {
+        self.inner.select(cs)?;
+        self.selected = Some(cs);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trace_entry_json_format() {
+        let trace = TraceRecorder::new();
+        trace.record(
+            "i2c",
+            TraceOp::I2cWrite {
+                address: 0x76,
+                data: vec![0xF4, 0x27],
+            },
+            None,
+        );
+        trace.record(
+            "gpio",
+            TraceOp::GpioSetMode {
+                pin: 4,
+                mode: PinMode::Alt(0),
+            },
+            Some("Simulated GPIO failure on pin 4".to_string()),
+        );
+
+        let json = serde_json::to_value(trace.untimed()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!([
+                {"seq": 0, "source": "i2c", "op": "i2c_write", "address": 0x76, "data": [0xF4, 0x27]},
+                {"seq": 1, "source": "gpio", "op": "gpio_set_mode", "pin": 4, "mode": {"Alt": 0},
+                 "error": "Simulated GPIO failure on pin 4"}
+            ])
+        );
+
+        let parsed: Vec<TraceEntry> = serde_json::from_str(&trace.to_json().unwrap()).unwrap();
+        assert_eq!(parsed, trace.entries());
+        assert_eq!(trace.entries_from("gpio").len(), 1);
+    }
+}
@@ -0,0 +1,167 @@
+//! Value Change Dump export of GPIO activity
+//!
+//! `MockGpio` keeps a timestamped record of every level it drives and every
+//! input level set from outside. [`VcdWriter`] turns that record into a VCD
+//! file with one wire per pin, which GTKWave and PulseView can open.
+
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+use std::time::Duration;
+
+/// A level put on a pin, by a `Gpio::write` or an external stimulus
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LevelChange {
+    /// Time on the mock's clock
+    pub at: Duration,
+    pub pin: u8,
+    pub high: bool,
+}
+
+/// Renders pin level changes as a VCD file with a 1 us timescale
+#[derive(Debug, Clone)]
+pub struct VcdWriter {
+    changes: Vec<LevelChange>,
+    names: BTreeMap<u8, String>,
+}
+
+impl VcdWriter {
+    pub fn new(changes: Vec<LevelChange>) -> Self {
+        Self {
+            changes,
+            names: BTreeMap::new(),
+        }
+    }
+
+    /// Label a pin's wire (default `gpioN`)
+    pub fn with_name(mut self, pin: u8, name: &str) -> Self {
+        self.names.insert(pin, name.to_string());
+        self
+    }
+
+    pub fn to_vcd(&self) -> String {
+        let mut changes = self.changes.clone();
+        changes.sort_by_key(|change| change.at);
+
+        let mut pins: Vec<u8> = changes.iter().map(|change| change.pin).collect();
+        pins.extend(self.names.keys());
+        pins.sort_unstable();
+        pins.dedup();
+        let ids: BTreeMap<u8, String> = pins
+            .iter()
+            .enumerate()
+            .map(|(index, &pin)| (pin, identifier(index)))
+            .collect();
+
+        // Writing to a String cannot fail
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "$version {} {} $end",
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION")
+        );
+        let _ = writeln!(out, "$timescale 1us $end");
+        let _ = writeln!(out, "$scope module gpio $end");
+        for (pin, id) in &ids {
+            let name = self
+                .names
+                .get(pin)
+                .cloned()
+                .unwrap_or_else(|| format!("gpio{}", pin));
+            let _ = writeln!(out, "$var wire 1 {} {} $end", id, name);
+        }
+        let _ = writeln!(out, "$upscope $end");
+        let _ = writeln!(out, "$enddefinitions $end");
+
+        // Every pin starts unknown until its first level
+        let _ = writeln!(out, "#0");
+        let _ = writeln!(out, "$dumpvars");
+        for id in ids.values() {
+            let _ = writeln!(out, "x{}", id);
+        }
+        let _ = writeln!(out, "$end");
+
+        let mut levels: BTreeMap<u8, bool> = BTreeMap::new();
+        let mut time = 0;
+        for change in changes {
+            if levels.insert(change.pin, change.high) == Some(change.high) {
+                continue;
+            }
+            let at = change.at.as_micros();
+            if at != time {
+                let _ = writeln!(out, "#{}", at);
+                time = at;
+            }
+            let _ = writeln!(out, "{}{}", u8::from(change.high), ids[&change.pin]);
+        }
+        out
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        std::fs::write(path, self.to_vcd())
+            .with_context(|| format!("Failed to write waveform {}", path.display()))
+    }
+}
+
+/// Short VCD identifier code built from the printable ASCII range
+fn identifier(mut index: usize) -> String {
+    const FIRST: u8 = b'!';
+    const COUNT: usize = (b'~' - b'!' + 1) as usize;
+
+    let mut id = String::new();
+    loop {
+        id.push((FIRST + (index % COUNT) as u8) as char);
+        index /= COUNT;
+        if index == 0 {
+            return id;
+        }
+        index -= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(at_us: u64, pin: u8, high: bool) -> LevelChange {
+        LevelChange {
+            at: Duration::from_micros(at_us),
+            pin,
+            high,
+        }
+    }
+
+    #[test]
+    fn test_vcd_lists_changes_per_pin() {
+        let vcd = VcdWriter::new(vec![
+            change(0, 17, false),
+            change(250, 27, true),
+            change(500, 17, true),
+            change(500, 27, true),
+            change(1500, 17, false),
+        ])
+        .with_name(17, "led")
+        .to_vcd();
+
+        let body = vcd.split_once("$enddefinitions $end\n").unwrap();
+        assert!(body.0.contains("$timescale 1us $end"));
+        assert!(body.0.contains("$var wire 1 ! led $end"));
+        assert!(body.0.contains("$var wire 1 \" gpio27 $end"));
+        assert_eq!(
+            body.1,
+            "#0\n$dumpvars\nx!\nx\"\n$end\n0!\n#250\n1\"\n#500\n1!\n#1500\n0!\n"
+        );
+    }
+
+    #[test]
+    fn test_identifiers_are_unique() {
+        assert_eq!(identifier(0), "!");
+        assert_eq!(identifier(93), "~");
+        assert_eq!(identifier(94), "!!");
+        let ids: std::collections::HashSet<_> = (0..300).map(identifier).collect();
+        assert_eq!(ids.len(), 300);
+    }
+}
@@ -0,0 +1,227 @@
+//! Input signals defined over time
+//!
+//! A [`Waveform`] gives the level of an input at any instant on the mock's
+//! clock, so a driver sees the same signal however often it polls. Attach one
+//! to a pin with `MockGpio::set_input_waveform` and advance a `VirtualClock`
+//! to move along it.
+
+use super::rng::SeededRng;
+use std::time::Duration;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Shape {
+    /// Sorted level changes at absolute times
+    Steps {
+        initial: bool,
+        changes: Vec<(Duration, bool)>,
+    },
+    /// Endless square wave that rises at time zero
+    Square { high: Duration, low: Duration },
+}
+
+/// Level of an input pin as a function of time
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Waveform {
+    shape: Shape,
+    inverted: bool,
+}
+
+impl Waveform {
+    fn from_shape(shape: Shape) -> Self {
+        Self {
+            shape,
+            inverted: false,
+        }
+    }
+
+    /// A level that never changes
+    pub fn constant(high: bool) -> Self {
+        Self::steps(high, Vec::new())
+    }
+
+    /// Start at `initial` and switch to each level at its time
+    pub fn steps(initial: bool, mut changes: Vec<(Duration, bool)>) -> Self {
+        changes.sort_by_key(|&(at, _)| at);
+        Self::from_shape(Shape::Steps { initial, changes })
+    }
+
+    /// Square wave with a 50% duty cycle, high for the first half of each period
+    pub fn square(period: Duration) -> Self {
+        let high = period / 2;
+        Self::square_with_duty(high, period - high)
+    }
+
+    /// Square wave high for `high` then low for `low`, repeating from time zero
+    ///
+    /// Panics if the period is zero.
+    pub fn square_with_duty(high: Duration, low: Duration) -> Self {
+        assert!(
+            !(high + low).is_zero(),
+            "Square wave period must be non-zero"
+        );
+        Self::from_shape(Shape::Square { high, low })
+    }
+
+    /// `count` high pulses of `width`, one every `interval` from `start`
+    pub fn pulses(start: Duration, width: Duration, interval: Duration, count: usize) -> Self {
+        let mut changes = Vec::with_capacity(count * 2);
+        for n in 0..count as u32 {
+            let rise = start + interval * n;
+            changes.push((rise, true));
+            changes.push((rise + width, false));
+        }
+        Self::steps(false, changes)
+    }
+
+    /// Switch away from `initial` at `at`, bouncing at random until `at + settle`
+    ///
+    /// The same seed always gives the same bounce, as with a real contact
+    /// that bounces a handful of times before it settles.
+    pub fn bounce(initial: bool, at: Duration, settle: Duration, seed: u64) -> Self {
+        let mut rng = SeededRng::new(seed);
+        let settle_us = settle.as_micros() as u64;
+
+        // An odd number of changes, all distinct, so the level ends flipped
+        let bounces = (1 + rng.below(4)).min(settle_us / 2);
+        let mut offsets = if bounces == 0 {
+            vec![0]
+        } else {
+            vec![0, settle_us]
+        };
+        while (offsets.len() as u64) < 1 + bounces * 2 {
+            let offset = 1 + rng.below(settle_us - 1);
+            if !offsets.contains(&offset) {
+                offsets.push(offset);
+            }
+        }
+        offsets.sort_unstable();
+
+        let changes = offsets
+            .into_iter()
+            .enumerate()
+            .map(|(n, offset)| (at + Duration::from_micros(offset), (n % 2 == 0) != initial))
+            .collect();
+        Self::steps(initial, changes)
+    }
+
+    /// Flip every level, e.g. for an active-low signal
+    pub fn inverted(mut self) -> Self {
+        self.inverted = !self.inverted;
+        self
+    }
+
+    /// Level at time `at`; a change takes effect at its own instant
+    pub fn level_at(&self, at: Duration) -> bool {
+        let level = match &self.shape {
+            Shape::Steps { initial, changes } => changes
+                .iter()
+                .take_while(|&&(time, _)| time <= at)
+                .last()
+                .map_or(*initial, |&(_, level)| level),
+            Shape::Square { high, low } => {
+                let period = (*high + *low).as_nanos();
+                at.as_nanos() % period < high.as_nanos()
+            }
+        };
+        level != self.inverted
+    }
+
+    /// Level changes after `from` up to and including `to`, in order
+    pub fn changes_between(&self, from: Duration, to: Duration) -> Vec<(Duration, bool)> {
+        let mut changes = Vec::new();
+        match &self.shape {
+            Shape::Steps {
+                initial,
+                changes: steps,
+            } => {
+                let mut level = *initial;
+                for &(at, next) in steps {
+                    if at > to {
+                        break;
+                    }
+                    if at > from && next != level {
+                        changes.push((at, next));
+                    }
+                    level = next;
+                }
+            }
+            Shape::Square { high, low } => {
+                // A zero-length phase leaves the level unchanged
+                if high.is_zero() || low.is_zero() {
+                    return changes;
+                }
+                // Whole nanoseconds, so fast waves do not overflow the cycle count
+                let period_ns = (*high + *low).as_nanos() as u64;
+                let first = from.as_nanos() as u64 / period_ns;
+                let last = to.as_nanos() as u64 / period_ns;
+                for cycle in first..=last {
+                    let rise = Duration::from_nanos(period_ns * cycle);
+                    for (at, level) in [(rise, true), (rise + *high, false)] {
+                        if at > from && at <= to {
+                            changes.push((at, level));
+                        }
+                    }
+                }
+            }
+        }
+        for change in &mut changes {
+            change.1 ^= self.inverted;
+        }
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn us(value: u64) -> Duration {
+        Duration::from_micros(value)
+    }
+
+    #[test]
+    fn test_waveform_levels_and_changes() {
+        let clock = Waveform::square(us(100));
+        assert!(clock.level_at(us(0)));
+        assert!(!clock.level_at(us(50)));
+        assert!(clock.level_at(us(120)));
+        assert_eq!(
+            clock.changes_between(us(0), us(150)),
+            vec![(us(50), false), (us(100), true), (us(150), false)]
+        );
+
+        // Far past the point where a 32-bit cycle count for 1 us would wrap
+        let fast = Waveform::square(us(1));
+        let late = Duration::from_secs(2 * 60 * 60);
+        assert_eq!(
+            fast.changes_between(late, late + Duration::from_nanos(1000)),
+            vec![
+                (late + Duration::from_nanos(500), false),
+                (late + Duration::from_nanos(1000), true)
+            ]
+        );
+
+        // A phase of zero never changes level, however long the window
+        let stuck = Waveform::square_with_duty(Duration::from_nanos(1), Duration::ZERO);
+        assert!(stuck.changes_between(Duration::ZERO, late).is_empty());
+
+        let train = Waveform::pulses(us(10), us(5), us(20), 2).inverted();
+        assert!(train.level_at(us(0)));
+        assert!(!train.level_at(us(12)));
+        assert_eq!(
+            train.changes_between(us(10), us(100)),
+            vec![(us(15), true), (us(30), false), (us(35), true)]
+        );
+
+        let bounce = Waveform::bounce(true, us(1000), us(500), 7);
+        assert_eq!(bounce, Waveform::bounce(true, us(1000), us(500), 7));
+        assert!(bounce.level_at(us(999)));
+        assert!(!bounce.level_at(us(1000)));
+        assert!(!bounce.level_at(us(1500)));
+        let changes = bounce.changes_between(us(0), us(2000));
+        assert!(changes.len() >= 3 && changes.len() % 2 == 1);
+        assert!(changes
+            .windows(2)
+            .all(|w| w[0].0 < w[1].0 && w[0].1 != w[1].1));
+    }
+}
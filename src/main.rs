@@ -4,7 +4,8 @@ use log::{info, warn};
 use serde_json::json;
 use std::process;
 
-use my_rust_pi_app::hw::{Gpio, MockGpio};
+use my_rust_pi_app::hw::scan::scan_and_identify;
+use my_rust_pi_app::hw::{Gpio, I2c, MockGpio, PinMode};
 
 fn main() -> Result<()> {
     env_logger::init();
@@ -24,6 +25,20 @@ fn main() -> Result<()> {
                 .help("Run self-test without real hardware")
                 .action(clap::ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("gpio-chip")
+                .long("gpio-chip")
+                .value_name("PATH")
+                .help(
+                    "Drive a real GPIO character device (e.g. /dev/gpiochip0) instead of the mock",
+                ),
+        )
+        .arg(
+            Arg::new("i2c-scan")
+                .long("i2c-scan")
+                .value_name("PATH")
+                .help("Scan an I2C bus (e.g. /dev/i2c-1), identify devices and exit"),
+        )
         .get_matches();
 
     if matches.get_flag("healthcheck") {
@@ -34,11 +49,15 @@ fn main() -> Result<()> {
         return run_self_test();
     }
 
+    if let Some(bus) = matches.get_one::<String>("i2c-scan") {
+        return run_i2c_scan(bus);
+    }
+
     info!("Starting Raspberry Pi application");
     println!("Hello from Raspberry Pi!");
 
-    // Simulate some basic GPIO operations using mock hardware
-    let mut gpio = MockGpio::new();
+    let mut gpio = open_gpio(matches.get_one::<String>("gpio-chip"))?;
+    gpio.set_mode(18, PinMode::Output)?;
     gpio.write(18, true)?;
     let pin_state = gpio.read(18)?;
     info!("GPIO pin 18 state: {}", pin_state);
@@ -46,6 +65,70 @@ fn main() -> Result<()> {
     Ok(())
 }
 
+/// Open the requested GPIO backend, falling back to mock hardware
+fn open_gpio(chip: Option<&String>) -> Result<Box<dyn Gpio>> {
+    match chip {
+        None => Ok(Box::new(MockGpio::new())),
+        #[cfg(feature = "gpio-cdev")]
+        Some(path) => Ok(Box::new(my_rust_pi_app::hw::CdevGpio::open(path)?)),
+        #[cfg(not(feature = "gpio-cdev"))]
+        Some(_) => Err(anyhow::anyhow!(
+            "--gpio-chip requires building with the gpio-cdev feature"
+        )),
+    }
+}
+
+/// Open a real I2C bus; there is no mock fallback since scanning one is meaningless
+fn open_i2c(bus: &str) -> Result<Box<dyn I2c>> {
+    #[cfg(feature = "i2c-dev")]
+    {
+        Ok(Box::new(my_rust_pi_app::hw::I2cDev::open(bus)?))
+    }
+    #[cfg(not(feature = "i2c-dev"))]
+    {
+        Err(anyhow::anyhow!(
+            "--i2c-scan {} requires building with the i2c-dev feature",
+            bus
+        ))
+    }
+}
+
+fn run_i2c_scan(bus: &str) -> Result<()> {
+    info!("Scanning I2C bus {}", bus);
+
+    let mut i2c = open_i2c(bus)?;
+    let devices: Vec<_> = scan_and_identify(i2c.as_mut())
+        .into_iter()
+        .map(|result| {
+            let candidates: Vec<_> = result
+                .candidates
+                .iter()
+                .map(|c| {
+                    json!({
+                        "name": c.device.name,
+                        "description": c.device.description,
+                        "confirmed": c.confirmed
+                    })
+                })
+                .collect();
+            json!({
+                "address": format!("0x{:02X}", result.address),
+                "candidates": candidates
+            })
+        })
+        .collect();
+
+    let result = json!({
+        "i2c_scan": {
+            "bus": bus,
+            "devices": devices
+        }
+    });
+
+    println!("{}", serde_json::to_string_pretty(&result)?);
+    Ok(())
+}
+
 fn run_healthcheck() -> Result<()> {
     info!("Running health check");
 
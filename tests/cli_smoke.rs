@@ -45,3 +45,12 @@ fn default_run_succeeds() {
         .success()
         .stdout(predicate::str::contains("Hello from Raspberry Pi!"));
 }
+
+#[test]
+fn i2c_scan_fails_without_bus() {
+    let mut cmd = Command::cargo_bin("my-rust-pi-app").unwrap();
+    cmd.args(["--i2c-scan", "/nonexistent/i2c-99"]);
+    cmd.assert()
+        .failure()
+        .stderr(predicate::str::contains("i2c-99"));
+}
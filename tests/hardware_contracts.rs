@@ -1,5 +1,8 @@
 use my_rust_pi_app::hw::*;
 use serial_test::serial;
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::Duration;
 
 #[test]
 #[serial]
@@ -49,6 +52,8 @@ fn gpio_mock_failure_injection() {
 #[serial]
 fn i2c_mock_logs_writes() {
     let mut i2c = MockI2c::new();
+    i2c.add_address(0x48);
+    i2c.add_address(0x49);
 
     i2c.write(0x48, &[0x01, 0x02, 0x03]).unwrap();
     i2c.write(0x49, &[0xFF]).unwrap();
@@ -85,6 +90,7 @@ fn i2c_mock_address_failures() {
     assert!(i2c.read(0x50, &mut buffer).is_err());
 
     // Other addresses should work
+    i2c.add_address(0x51);
     assert!(i2c.write(0x51, &[0x01]).is_ok());
 }
 
@@ -134,3 +140,1054 @@ fn spi_mock_failure_injection() {
     spi.set_failure(false);
     assert!(spi.transfer(&mut data).is_ok());
 }
+
+#[test]
+#[serial]
+fn spi_bus_shares_controller_between_devices() {
+    let gpio = Rc::new(RefCell::new(MockGpio::new()));
+    let bus = SpiBus::new(MockSpi::new()).with_cs_gpio(gpio.clone());
+    let mut flash = bus
+        .device(ChipSelect::Hardware(0), SpiConfig::default())
+        .unwrap();
+    let mut sensor = bus
+        .device(
+            ChipSelect::Gpio(5),
+            SpiConfig::new(SpiMode::Mode3, 4_000_000),
+        )
+        .unwrap();
+
+    flash.transfer(&mut [0x9F, 0x00, 0x00, 0x00]).unwrap();
+    sensor.transfer(&mut [0xF5, 0x00]).unwrap();
+    flash.transfer(&mut [0x05, 0x00]).unwrap();
+
+    let spi = bus.controller();
+    assert_eq!(
+        spi.get_target_log(),
+        &[
+            Some(ChipSelect::Hardware(0)),
+            Some(ChipSelect::Gpio(5)),
+            Some(ChipSelect::Hardware(0)),
+        ]
+    );
+    assert_eq!(
+        spi.get_transfers_to(ChipSelect::Gpio(5)),
+        vec![vec![0xF5, 0x00]]
+    );
+    assert_eq!(spi.get_config(), SpiConfig::default());
+
+    // GPIO chip select idles high and is pulsed low once for its transfer
+    assert_eq!(gpio.borrow().get_write_count(5), 3);
+    assert!(gpio.borrow_mut().read(5).unwrap());
+}
+
+#[test]
+#[serial]
+fn spi_transaction_holds_chip_select() {
+    let gpio = Rc::new(RefCell::new(MockGpio::new()));
+    let mut spi = MockSpi::new();
+    spi.add_response(vec![0xDE, 0xAD, 0xBE, 0xEF]);
+    let bus = SpiBus::new(spi).with_cs_gpio(gpio.clone());
+    let mut flash = bus
+        .device(ChipSelect::Gpio(7), SpiConfig::default())
+        .unwrap();
+
+    // READ DATA: opcode and 24-bit address, then the data, all under one CS
+    let mut data = [0u8; 4];
+    flash
+        .transaction(&mut [
+            SpiOperation::Write(&[0x03, 0x00, 0x10, 0x00]),
+            SpiOperation::Read(&mut data),
+        ])
+        .unwrap();
+    flash.write(&[0x04]).unwrap();
+
+    assert_eq!(data, [0xDE, 0xAD, 0xBE, 0xEF]);
+    assert_eq!(gpio.borrow().get_write_count(7), 5);
+    assert_eq!(
+        bus.controller().get_transaction_log(),
+        &[
+            vec![
+                SpiOpRecord::Write(vec![0x03, 0x00, 0x10, 0x00]),
+                SpiOpRecord::Read(vec![0xDE, 0xAD, 0xBE, 0xEF]),
+            ],
+            vec![SpiOpRecord::Write(vec![0x04])],
+        ]
+    );
+}
+
+#[test]
+#[serial]
+fn spi_responders_model_chips_per_device() {
+    let mut spi = MockSpi::new();
+    let flash = spi.attach_responder(
+        ChipSelect::Hardware(0),
+        CommandResponder::new()
+            .with_reply(0x9F, vec![0xEF, 0x40, 0x18])
+            .with_reply(0x05, vec![0x01]),
+    );
+    // A 12-bit ADC that echoes the channel it was asked for
+    spi.attach_responder(
+        ChipSelect::Hardware(1),
+        spi_responder::from_fn(|sent: &[u8]| vec![0x00, sent[1] >> 4, 0xFF]),
+    );
+    let bus = SpiBus::new(spi);
+    let mut flash_dev = bus
+        .device(ChipSelect::Hardware(0), SpiConfig::default())
+        .unwrap();
+    let mut adc = bus
+        .device(ChipSelect::Hardware(1), SpiConfig::default())
+        .unwrap();
+
+    // Order of calls doesn't matter: each chip answers its own commands
+    let mut sample = [0x01, 0xA0, 0x00];
+    adc.transfer(&mut sample).unwrap();
+    let mut status = [0u8; 1];
+    flash_dev.write_read(&[0x05], &mut status).unwrap();
+    let mut id = [0u8; 3];
+    flash_dev.write_read(&[0x9F], &mut id).unwrap();
+
+    assert_eq!(sample, [0x00, 0x0A, 0xFF]);
+    assert_eq!(status, [0x01]);
+    assert_eq!(id, [0xEF, 0x40, 0x18]);
+
+    flash.lock().unwrap().set_reply(0x05, vec![0x00]);
+    flash_dev.write_read(&[0x05], &mut status).unwrap();
+    assert_eq!(status, [0x00]);
+    assert_eq!(flash.lock().unwrap().get_command_count(0x05), 2);
+}
+
+#[test]
+#[serial]
+fn spi_mock_catches_wrong_mode() {
+    let mut chip = MockSpi::new();
+    chip.require_config(SpiConfig::new(SpiMode::Mode3, 8_000_000));
+    let bus = SpiBus::new(chip);
+
+    let mut wrong = bus
+        .device(
+            ChipSelect::Hardware(0),
+            SpiConfig::new(SpiMode::Mode0, 8_000_000),
+        )
+        .unwrap();
+    let err = wrong.transfer(&mut [0x75, 0x00]).unwrap_err();
+    assert!(err.to_string().contains("mode 3"));
+
+    wrong
+        .configure(&SpiConfig::new(SpiMode::Mode3, 8_000_000))
+        .unwrap();
+    wrong.transfer(&mut [0x75, 0x00]).unwrap();
+    assert_eq!(
+        bus.controller().get_config_log(),
+        &[SpiConfig::new(SpiMode::Mode3, 8_000_000)]
+    );
+}
+
+#[test]
+#[serial]
+fn gpio_mock_enforces_pin_modes() {
+    let mut gpio = MockGpio::new();
+    gpio.set_mode(10, PinMode::Input).unwrap();
+    gpio.set_mode(11, PinMode::Alt(0)).unwrap();
+    gpio.set_mode(12, PinMode::Output).unwrap();
+
+    assert!(gpio.write(10, true).is_err());
+    assert!(gpio.write(11, true).is_err());
+    assert!(gpio.write(12, true).is_ok());
+
+    // Rejected writes are not counted
+    assert_eq!(gpio.get_write_count(10), 0);
+    assert_eq!(gpio.get_write_count(12), 1);
+}
+
+#[test]
+#[serial]
+fn gpio_mock_flags_floating_inputs() {
+    let mut gpio = MockGpio::new();
+    gpio.set_mode(20, PinMode::Input).unwrap();
+    gpio.set_mode(21, PinMode::Input).unwrap();
+    gpio.set_pull(21, Pull::Down).unwrap();
+
+    gpio.read(20).unwrap();
+    gpio.read(21).unwrap();
+
+    assert_eq!(gpio.get_floating_read_count(20), 1);
+    assert_eq!(gpio.get_floating_read_count(21), 0);
+    assert_eq!(gpio.get_pull(21), Pull::Down);
+
+    // Scripted responses model an external driver, so the pin is not floating
+    gpio.set_scripted_responses(20, vec![true]);
+    assert!(gpio.read(20).unwrap());
+    assert_eq!(gpio.get_floating_read_count(20), 1);
+}
+
+#[test]
+#[serial]
+fn gpio_mock_events_from_scripted_responses() {
+    let mut gpio = MockGpio::new();
+    gpio.set_mode(17, PinMode::Input).unwrap();
+    gpio.set_scripted_responses(17, vec![false, true, true, false]);
+    gpio.subscribe(17, Edge::Both).unwrap();
+
+    let timeout = Duration::from_millis(100);
+    let first = gpio.wait_for_edge(timeout).unwrap().unwrap();
+    let second = gpio.wait_for_edge(timeout).unwrap().unwrap();
+
+    assert_eq!((first.pin, first.edge), (17, Edge::Rising));
+    assert_eq!((second.pin, second.edge), (17, Edge::Falling));
+
+    // Script exhausted: the wait times out
+    assert!(gpio.wait_for_edge(timeout).unwrap().is_none());
+}
+
+#[test]
+#[serial]
+fn gpio_mock_event_subscription_lifecycle() {
+    let mut gpio = MockGpio::new();
+    gpio.set_mode(22, PinMode::Output).unwrap();
+    assert!(gpio.subscribe(22, Edge::Both).is_err());
+
+    gpio.set_mode(23, PinMode::Input).unwrap();
+    gpio.set_pull(23, Pull::Up).unwrap();
+    gpio.subscribe(23, Edge::Falling).unwrap();
+
+    // Pulled-up input starts high, so driving it high again is not an edge
+    gpio.set_input_level(23, true);
+    assert_eq!(gpio.get_pending_event_count(), 0);
+
+    gpio.set_input_level(23, false);
+    assert_eq!(gpio.get_pending_event_count(), 1);
+    assert!(!gpio.read(23).unwrap());
+
+    gpio.unsubscribe(23).unwrap();
+    assert_eq!(gpio.get_pending_event_count(), 0);
+    gpio.set_input_level(23, true);
+    assert_eq!(gpio.events().count(), 0);
+}
+
+#[test]
+#[serial]
+fn i2c_mock_answers_reads_by_preceding_write() {
+    let mut i2c = MockI2c::new();
+    i2c.set_read_response(0x48, vec![0x00]);
+    i2c.set_write_read_response(0x48, &[0x01], vec![0x85, 0x83]);
+
+    // Register pointer set with a separate write (STOP in between)
+    i2c.write(0x48, &[0x01]).unwrap();
+    let mut config = [0u8; 2];
+    assert_eq!(i2c.read(0x48, &mut config).unwrap(), 2);
+    assert_eq!(config, [0x85, 0x83]);
+
+    // Unknown pointer falls back to the per-address response
+    let mut other = [0u8; 1];
+    i2c.write_read(0x48, &[0x02], &mut other).unwrap();
+    assert_eq!(other, [0x00]);
+}
+
+#[test]
+#[serial]
+fn i2c_mock_records_transactions_as_a_unit() {
+    let mut i2c = MockI2c::new();
+    i2c.set_write_read_response(0x68, &[0x00], vec![0x30, 0x59]);
+
+    let mut time = [0u8; 2];
+    i2c.transaction(
+        0x68,
+        &mut [I2cOperation::Write(&[0x00]), I2cOperation::Read(&mut time)],
+    )
+    .unwrap();
+
+    let log = i2c.get_transaction_log();
+    assert_eq!(log.len(), 1);
+    assert_eq!(log[0].0, 0x68);
+    assert_eq!(
+        log[0].1,
+        vec![
+            I2cOpRecord::Write(vec![0x00]),
+            I2cOpRecord::Read(vec![0x30, 0x59])
+        ]
+    );
+
+    i2c.set_address_failure(0x69);
+    assert!(i2c.write_read(0x69, &[0x00], &mut time).is_err());
+    assert_eq!(i2c.get_transaction_log().len(), 1);
+}
+
+#[test]
+#[serial]
+fn i2c_mock_register_map_device() {
+    let mut i2c = MockI2c::new();
+    let bme280 = i2c.attach_device(
+        0x76,
+        RegisterMap::new()
+            .with_register(0xD0, 0x60, RegisterAccess::ReadOnly)
+            .with_register(0xF4, 0x00, RegisterAccess::ReadWrite),
+    );
+    bme280.lock().unwrap().set_register(0xFA, 0x80);
+    bme280.lock().unwrap().set_register(0xFB, 0x01);
+
+    let mut chip_id = [0u8; 1];
+    i2c.write_read(0x76, &[0xD0], &mut chip_id).unwrap();
+    assert_eq!(chip_id, [0x60]);
+
+    // Register write through the bus lands in the model
+    i2c.write(0x76, &[0xF4, 0x27]).unwrap();
+    assert_eq!(bme280.lock().unwrap().get_register(0xF4), 0x27);
+
+    // Burst read auto-increments across registers
+    let mut temp = [0u8; 2];
+    i2c.write_read(0x76, &[0xFA], &mut temp).unwrap();
+    assert_eq!(temp, [0x80, 0x01]);
+}
+
+/// Custom behaviour: a counter that returns its next value on every read
+#[derive(Debug, Default)]
+struct CountingDevice {
+    next: u8,
+    resets: usize,
+}
+
+impl I2cDevice for CountingDevice {
+    fn write(&mut self, _data: &[u8]) -> I2cResult<()> {
+        self.next = 0;
+        self.resets += 1;
+        Ok(())
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> I2cResult<()> {
+        for byte in buffer {
+            *byte = self.next;
+            self.next += 1;
+        }
+        Ok(())
+    }
+}
+
+#[test]
+#[serial]
+fn i2c_mock_custom_device_behaviour() {
+    let mut i2c = MockI2c::new();
+    let counter = i2c.attach_device(0x20, CountingDevice::default());
+
+    let mut buffer = [0u8; 3];
+    assert_eq!(i2c.read(0x20, &mut buffer).unwrap(), 3);
+    assert_eq!(buffer, [0, 1, 2]);
+
+    i2c.write_read(0x20, &[0x00], &mut buffer).unwrap();
+    assert_eq!(buffer, [0, 1, 2]);
+    assert_eq!(counter.lock().unwrap().resets, 1);
+}
+
+#[test]
+#[serial]
+fn i2c_mock_typed_bus_errors() {
+    let mut i2c = MockI2c::new();
+    i2c.set_address_error(0x40, I2cError::Timeout);
+    i2c.set_address_error(0x41, I2cError::DataNack(0x41));
+
+    let mut buffer = [0u8; 2];
+    assert_eq!(i2c.read(0x40, &mut buffer), Err(I2cError::Timeout));
+    assert_eq!(
+        i2c.write_read(0x41, &[0x00], &mut buffer),
+        Err(I2cError::DataNack(0x41))
+    );
+
+    // Nothing registered at 0x42: "no device present", distinguishable from the above
+    let err = i2c.write(0x42, &[0x00]).unwrap_err();
+    assert_eq!(err, I2cError::AddressNack(0x42));
+    assert_eq!(err.to_string(), "No ACK from I2C address 0x42");
+
+    // Typed errors still convert into anyhow for application code
+    let result: anyhow::Result<()> = i2c.write(0x42, &[0x00]).map_err(Into::into);
+    assert!(result.unwrap_err().downcast_ref::<I2cError>().is_some());
+}
+
+/// A device that NACKs any write longer than its single register
+#[derive(Debug)]
+struct SingleRegisterDevice;
+
+impl I2cDevice for SingleRegisterDevice {
+    fn write(&mut self, data: &[u8]) -> I2cResult<()> {
+        if data.len() > 1 {
+            return Err(I2cError::DataNack(0x27));
+        }
+        Ok(())
+    }
+
+    fn read(&mut self, buffer: &mut [u8]) -> I2cResult<()> {
+        buffer.fill(0xFF);
+        Ok(())
+    }
+}
+
+#[test]
+#[serial]
+fn i2c_mock_device_can_nack_data() {
+    let mut i2c = MockI2c::new();
+    i2c.attach_device(0x27, SingleRegisterDevice);
+
+    assert!(i2c.write(0x27, &[0x01]).is_ok());
+    assert_eq!(
+        i2c.write(0x27, &[0x01, 0x02]),
+        Err(I2cError::DataNack(0x27))
+    );
+    assert_eq!(i2c.get_write_log().len(), 1);
+}
+
+#[test]
+#[serial]
+fn i2c_scan_finds_and_identifies_devices() {
+    let mut i2c = MockI2c::new();
+    i2c.attach_device(
+        0x68,
+        RegisterMap::new().with_register(0x75, 0x68, RegisterAccess::ReadOnly),
+    );
+    i2c.attach_device(
+        0x76,
+        RegisterMap::new().with_register(0xD0, 0x60, RegisterAccess::ReadOnly),
+    );
+    i2c.add_address(0x3C);
+    i2c.add_address(0x50);
+    // Reserved addresses are never probed
+    i2c.add_address(0x03);
+
+    assert_eq!(scan::scan_bus(&mut i2c), vec![0x3C, 0x50, 0x68, 0x76]);
+
+    let results = scan::scan_and_identify(&mut i2c);
+    let mpu = &results[2];
+    assert_eq!(mpu.address, 0x68);
+    assert_eq!(mpu.candidates[0].device.name, "MPU-6050");
+    assert!(mpu.candidates[0].confirmed);
+
+    // A multiplexer may sit at 0x76, so it is never written to
+    let bme280 = &results[3];
+    assert_eq!(bme280.address, 0x76);
+    assert!(bme280.candidates.iter().all(|c| !c.confirmed));
+    assert!(bme280
+        .candidates
+        .iter()
+        .any(|c| c.device.name == "TCA9548A"));
+
+    assert_eq!(results[0].candidates[0].device.name, "SSD1306");
+    assert!(!results[0].candidates[0].confirmed);
+}
+
+#[test]
+#[serial]
+fn strict_mocks_verify_driver_sequence() {
+    let mut gpio = MockGpio::new();
+    gpio.expect(vec![
+        GpioExpectation::SetMode(22, PinMode::Output),
+        GpioExpectation::Write(22, true),
+    ]);
+    gpio.set_mode(22, PinMode::Output).unwrap();
+    gpio.write(22, true).unwrap();
+    gpio.done().unwrap();
+
+    let mut i2c = MockI2c::new();
+    i2c.set_address_error(0x77, I2cError::AddressNack(0x77));
+    i2c.expect(vec![
+        I2cExpectation::write_read(0x76, vec![0xD0], vec![0x60]),
+        I2cExpectation::Write {
+            address: 0x76,
+            data: vec![0xF4, 0x27],
+        },
+    ]);
+    let mut id = [0u8; 1];
+    // A scripted NACK fails the call before it is matched against expectations
+    assert_eq!(
+        i2c.write_read(0x77, &[0xD0], &mut id),
+        Err(I2cError::AddressNack(0x77))
+    );
+    i2c.write_read(0x76, &[0xD0], &mut id).unwrap();
+    assert_eq!(id, [0x60]);
+    i2c.write(0x76, &[0xF4, 0x27]).unwrap();
+    i2c.done().unwrap();
+
+    let mut spi = MockSpi::new();
+    spi.expect(vec![
+        SpiExpectation::write_read(vec![0x9F], vec![0xEF, 0x40, 0x18]).on(ChipSelect::Hardware(0)),
+        SpiExpectation::transfer(vec![0x05, 0x00], vec![0x00, 0x02]),
+    ]);
+    let bus = SpiBus::new(spi);
+    let mut flash = bus
+        .device(ChipSelect::Hardware(0), SpiConfig::default())
+        .unwrap();
+    let mut jedec = [0u8; 3];
+    flash.write_read(&[0x9F], &mut jedec).unwrap();
+    let mut status = [0x05, 0x00];
+    flash.transfer(&mut status).unwrap();
+    assert_eq!(jedec, [0xEF, 0x40, 0x18]);
+    assert_eq!(status, [0x00, 0x02]);
+    bus.controller().done().unwrap();
+}
+
+#[test]
+#[serial]
+fn strict_mocks_reject_unexpected_and_missing_operations() {
+    let mut i2c = MockI2c::new();
+    i2c.expect(vec![I2cExpectation::Read {
+        address: 0x48,
+        reply: vec![0x12, 0x34],
+    }]);
+    let err = i2c.write(0x48, &[0x01]).unwrap_err();
+    assert!(matches!(err, I2cError::Other(_)));
+    assert!(i2c.done().is_err());
+
+    let mut spi = MockSpi::new();
+    spi.expect(vec![
+        SpiExpectation::write(vec![0x06]),
+        SpiExpectation::write(vec![0x02, 0x00, 0x00, 0x00, 0xAA]),
+    ]);
+    spi.write(&[0x06]).unwrap();
+    let err = spi.done().unwrap_err();
+    assert!(err
+        .to_string()
+        .starts_with("1 of 2 SPI expectations not met"));
+}
+
+#[test]
+#[serial]
+fn trace_interleaves_all_buses() {
+    let trace = TraceRecorder::new();
+    let mut gpio = MockGpio::new();
+    gpio.set_trace(&trace, "gpio");
+    let mut i2c = MockI2c::new();
+    i2c.set_write_read_response(0x76, &[0xD0], vec![0x60]);
+    i2c.set_trace(&trace, "i2c-1");
+    let mut spi = MockSpi::new();
+    spi.add_response(vec![0xEF, 0x40]);
+    spi.set_trace(&trace, "spi0");
+
+    // Reset a sensor, read its ID, then poll a flash chip
+    gpio.set_mode(27, PinMode::Output).unwrap();
+    gpio.write(27, false).unwrap();
+    gpio.write(27, true).unwrap();
+    let mut id = [0u8; 1];
+    i2c.write_read(0x76, &[0xD0], &mut id).unwrap();
+    let mut jedec = [0u8; 2];
+    spi.write_read(&[0x9F], &mut jedec).unwrap();
+    assert!(i2c.write(0x48, &[0x01]).is_err());
+
+    let entries = trace.entries();
+    assert_eq!(entries.len(), 6);
+    assert!(entries.windows(2).all(|w| w[0].at_us <= w[1].at_us));
+    assert_eq!(trace.entries_from("i2c-1").len(), 2);
+
+    insta::assert_yaml_snapshot!("trace_interleaves_all_buses", trace.untimed());
+}
+
+#[test]
+#[serial]
+fn traced_wrapper_records_any_backend() {
+    let trace = TraceRecorder::new();
+    let mut gpio = Traced::new(MockGpio::new(), &trace, "gpio");
+    gpio.write(5, true).unwrap();
+    assert!(gpio.read(5).unwrap());
+
+    assert_eq!(
+        trace.ops(),
+        vec![
+            TraceOp::GpioWrite { pin: 5, high: true },
+            TraceOp::GpioRead { pin: 5, high: true },
+        ]
+    );
+    assert_eq!(gpio.inner().get_write_count(5), 1);
+}
+
+#[test]
+#[serial]
+fn recorded_session_replays_without_hardware() {
+    let dir = assert_fs::TempDir::new().unwrap();
+    let path = dir.path().join("session.json");
+
+    // Record a session against the backends
+    let trace = TraceRecorder::new();
+    let mut gpio = Traced::new(MockGpio::new(), &trace, "gpio");
+    let mut mock_i2c = MockI2c::new();
+    mock_i2c.set_write_read_response(0x76, &[0xD0], vec![0x60]);
+    let mut i2c = Traced::new(mock_i2c, &trace, "i2c-1");
+    let mut mock_spi = MockSpi::new();
+    mock_spi.add_response(vec![0x00, 0xEF, 0x40]);
+    let mut spi = Traced::new(mock_spi, &trace, "spi0");
+
+    gpio.set_mode(27, PinMode::Output).unwrap();
+    gpio.write(27, true).unwrap();
+    let mut id = [0u8; 1];
+    i2c.write_read(0x76, &[0xD0], &mut id).unwrap();
+    assert!(i2c.write(0x48, &[0x01]).is_err());
+    spi.select(ChipSelect::Hardware(0)).unwrap();
+    let mut jedec = [0x9F, 0x00, 0x00];
+    spi.transfer(&mut jedec).unwrap();
+    trace.save(&path).unwrap();
+
+    // Replay the same driver calls from the file
+    let session = ReplaySession::load(&path).unwrap();
+    let mut gpio = session.gpio("gpio");
+    let mut i2c = session.i2c("i2c-1");
+    let mut spi = session.spi("spi0");
+
+    gpio.set_mode(27, PinMode::Output).unwrap();
+    gpio.write(27, true).unwrap();
+    let mut replayed_id = [0u8; 1];
+    i2c.write_read(0x76, &[0xD0], &mut replayed_id).unwrap();
+    assert_eq!(replayed_id, id);
+    assert_eq!(i2c.write(0x48, &[0x01]), Err(I2cError::AddressNack(0x48)));
+    spi.select(ChipSelect::Hardware(0)).unwrap();
+    let mut replayed_jedec = [0x9F, 0x00, 0x00];
+    spi.transfer(&mut replayed_jedec).unwrap();
+    assert_eq!(replayed_jedec, [0x00, 0xEF, 0x40]);
+    session.done().unwrap();
+
+    // The typed error is stored, not parsed back from its message
+    let mut entries = trace.entries_from("i2c-1");
+    entries[1].i2c_error = None;
+    let mut i2c = ReplaySession::new(entries).i2c("i2c-1");
+    i2c.write_read(0x76, &[0xD0], &mut replayed_id).unwrap();
+    assert!(matches!(i2c.write(0x48, &[0x01]), Err(I2cError::Other(_))));
+
+    // A driver that drifts from the recording is caught
+    let session = ReplaySession::load(&path).unwrap();
+    let mut gpio = session.gpio("gpio");
+    gpio.set_mode(27, PinMode::Output).unwrap();
+    let err = gpio.write(27, false).unwrap_err();
+    assert!(err.to_string().contains("Unexpected GPIO operation #2"));
+    assert!(session.done().is_err());
+}
+
+#[test]
+#[serial]
+fn gpio_activity_exports_as_vcd() {
+    let dir = assert_fs::TempDir::new().unwrap();
+    let path = dir.path().join("blink.vcd");
+    let mut gpio = MockGpio::new();
+    gpio.set_mode(17, PinMode::Output).unwrap();
+    gpio.set_mode(22, PinMode::Input).unwrap();
+
+    gpio.write(17, true).unwrap();
+    gpio.set_input_level(22, true);
+    gpio.write(17, true).unwrap();
+    gpio.write(17, false).unwrap();
+
+    let changes = gpio.get_level_changes();
+    assert_eq!(changes.len(), 4);
+    assert!(changes.windows(2).all(|w| w[0].at <= w[1].at));
+
+    gpio.vcd().with_name(17, "led").save(&path).unwrap();
+    let vcd = std::fs::read_to_string(&path).unwrap();
+    assert!(vcd.contains("$var wire 1 ! led $end"));
+    assert!(vcd.contains("$var wire 1 \" gpio22 $end"));
+    // The repeated high write is not a change
+    assert_eq!(vcd.lines().filter(|line| line.ends_with('!')).count(), 3);
+}
+
+/// Driver-style helper written against the traits, as a bit-banged protocol would be
+fn pulse(
+    gpio: &mut impl Gpio,
+    delay: &mut impl Delay,
+    pin: u8,
+    width_us: u64,
+) -> anyhow::Result<()> {
+    gpio.write(pin, true)?;
+    delay.delay_us(width_us);
+    gpio.write(pin, false)
+}
+
+#[test]
+#[serial]
+fn virtual_clock_timestamps_mocks_and_trace() {
+    let clock = VirtualClock::new();
+    let trace = TraceRecorder::with_clock(clock.clone());
+    let mut gpio = MockGpio::new();
+    gpio.set_clock(clock.clone());
+    gpio.set_trace(&trace, "gpio");
+    gpio.subscribe(22, Edge::Rising).unwrap();
+
+    let mut delay = clock.clone();
+    pulse(&mut gpio, &mut delay, 17, 10).unwrap();
+    clock.advance(Duration::from_millis(1));
+    gpio.set_input_level(22, true);
+
+    let at_us: Vec<_> = trace.entries().iter().map(|entry| entry.at_us).collect();
+    assert_eq!(at_us, vec![Some(0), Some(10)]);
+    assert_eq!(
+        gpio.get_level_changes(),
+        vec![
+            LevelChange {
+                at: Duration::ZERO,
+                pin: 17,
+                high: true
+            },
+            LevelChange {
+                at: Duration::from_micros(10),
+                pin: 17,
+                high: false
+            },
+            LevelChange {
+                at: Duration::from_micros(1010),
+                pin: 22,
+                high: true
+            },
+        ]
+    );
+    let event = gpio.wait_for_edge(Duration::ZERO).unwrap().unwrap();
+    assert_eq!(event.timestamp, Duration::from_micros(1010));
+}
+
+#[test]
+#[serial]
+fn input_waveform_follows_virtual_time() {
+    let clock = VirtualClock::new();
+    let mut gpio = MockGpio::new();
+    gpio.set_clock(clock.clone());
+    gpio.set_mode(5, PinMode::Input).unwrap();
+    gpio.set_input_waveform(5, Waveform::square(Duration::from_millis(10)));
+
+    // Polling at any rate sees the level the signal has at that instant
+    let mut fast = Vec::new();
+    for _ in 0..5 {
+        fast.push(gpio.read(5).unwrap());
+        clock.advance(Duration::from_millis(3));
+    }
+    assert_eq!(fast, vec![true, true, false, false, true]);
+    assert_eq!(gpio.get_read_count(5), 5);
+
+    // A bouncing button press turns into timestamped edge events
+    gpio.set_mode(6, PinMode::Input).unwrap();
+    gpio.set_pull(6, Pull::Up).unwrap();
+    gpio.subscribe(6, Edge::Falling).unwrap();
+    let press_at = clock.now() + Duration::from_millis(1);
+    gpio.set_input_waveform(
+        6,
+        Waveform::bounce(true, press_at, Duration::from_micros(800), 42),
+    );
+    clock.advance(Duration::from_millis(5));
+
+    let events: Vec<_> = gpio.events().map(|event| event.unwrap()).collect();
+    assert!(!events.is_empty());
+    assert_eq!(events[0].timestamp, press_at);
+    assert!(events
+        .iter()
+        .all(|event| event.timestamp < press_at + Duration::from_micros(801)));
+    assert!(!gpio.read(6).unwrap());
+
+    // Waveform changes show up in the exported waveform too
+    let falls = gpio
+        .get_level_changes()
+        .iter()
+        .filter(|change| change.pin == 6 && !change.high)
+        .count();
+    assert_eq!(falls, events.len());
+}
+
+#[test]
+#[serial]
+fn circuit_wires_pins_across_mocks() {
+    let clock = VirtualClock::new();
+    let circuit = Circuit::new();
+    let mut controller = MockGpio::new();
+    controller.set_clock(clock.clone());
+    controller.set_circuit(&circuit, "controller");
+    let mut peripheral = MockGpio::new();
+    peripheral.set_clock(clock.clone());
+    peripheral.set_circuit(&circuit, "peripheral");
+
+    // Loopback on one board, and an interrupt line between the two
+    circuit
+        .connect("loopback", &[("controller", 20), ("controller", 21)])
+        .unwrap();
+    circuit
+        .connect("irq", &[("peripheral", 4), ("controller", 17)])
+        .unwrap();
+
+    controller.set_mode(20, PinMode::Output).unwrap();
+    controller.set_mode(21, PinMode::Input).unwrap();
+    controller.write(20, true).unwrap();
+    assert!(controller.read(21).unwrap());
+    controller.write(20, false).unwrap();
+    assert!(!controller.read(21).unwrap());
+
+    // The open line idles high through the controller's pull-up
+    controller.set_mode(17, PinMode::Input).unwrap();
+    controller.set_pull(17, Pull::Up).unwrap();
+    controller.subscribe(17, Edge::Falling).unwrap();
+    assert_eq!(circuit.net_state("irq"), Some(NetState::Pulled(true)));
+    assert!(controller.read(17).unwrap());
+
+    clock.advance(Duration::from_millis(2));
+    peripheral.set_mode(4, PinMode::Output).unwrap();
+    peripheral.write(4, false).unwrap();
+    let event = controller.wait_for_edge(Duration::ZERO).unwrap().unwrap();
+    assert_eq!(event.pin, 17);
+    assert_eq!(event.timestamp, Duration::from_millis(2));
+    assert_eq!(controller.get_floating_read_count(17), 0);
+
+    // Both boards driving the line is caught
+    controller.set_mode(17, PinMode::Output).unwrap();
+    controller.write(17, true).unwrap();
+    assert_eq!(circuit.net_state("irq"), Some(NetState::Contention));
+    let contention = &circuit.get_contentions()[0];
+    assert_eq!(contention.net, "irq");
+    assert_eq!(contention.drivers.len(), 2);
+    assert!(peripheral.set_mode(4, PinMode::Input).is_ok());
+    assert_eq!(circuit.net_state("irq"), Some(NetState::Driven(true)));
+}
+
+/// Application logic under test: a light that follows a PIR sensor, with a
+/// push button that forces it on
+fn motion_light_step(gpio: &mut MockGpio) -> anyhow::Result<()> {
+    let motion = gpio.read(4)?;
+    let forced = !gpio.read(17)?;
+    gpio.write(18, motion || forced)?;
+    gpio.write(23, motion)
+}
+
+#[test]
+#[serial]
+fn simulated_components_exercise_application_logic() {
+    let clock = VirtualClock::new();
+    let mut gpio = MockGpio::new();
+    gpio.set_clock(clock.clone());
+    gpio.set_mode(4, PinMode::Input).unwrap();
+    gpio.set_mode(17, PinMode::Input).unwrap();
+    gpio.set_pull(17, Pull::Up).unwrap();
+    gpio.set_mode(18, PinMode::Output).unwrap();
+    gpio.set_mode(23, PinMode::Output).unwrap();
+
+    let mut pir = Pir::new(4, Duration::from_secs(2));
+    let mut button = Button::new(17)
+        .active_low()
+        .with_bounce(Duration::from_millis(3), 9);
+    let led = Led::new(18);
+    let relay = Relay::new(23, Duration::from_millis(15));
+
+    let run_for = |gpio: &mut MockGpio, ms: u64| {
+        for _ in 0..ms / 10 {
+            motion_light_step(gpio).unwrap();
+            clock.advance(Duration::from_millis(10));
+        }
+    };
+
+    run_for(&mut gpio, 100);
+    pir.motion(&mut gpio);
+    run_for(&mut gpio, 1000);
+    pir.motion(&mut gpio);
+    run_for(&mut gpio, 3000);
+
+    assert_eq!(pir.get_detection_count(), 1);
+    assert_eq!(led.get_flash_count(&gpio), 1);
+    assert_eq!(led.get_on_time(&gpio), Duration::from_millis(3000));
+    assert_eq!(relay.get_close_count(&gpio), 1);
+    assert!(!relay.is_closed(&gpio));
+
+    // Polling well after the bounce settles sees one clean press
+    button.press(&mut gpio);
+    run_for(&mut gpio, 500);
+    assert!(led.is_on(&gpio));
+    button.release(&mut gpio);
+    run_for(&mut gpio, 100);
+    assert!(!led.is_on(&gpio));
+    assert_eq!(led.get_flash_count(&gpio), 2);
+    assert_eq!(button.get_press_count(), 1);
+}
+
+/// Retry loop of the kind the fault plans are meant to exercise
+fn read_register_with_retry(i2c: &mut MockI2c, address: u8, register: u8) -> I2cResult<u8> {
+    let mut last_error = I2cError::Timeout;
+    for _ in 0..3 {
+        let mut value = [0u8; 1];
+        match i2c.write_read(address, &[register], &mut value) {
+            Ok(()) => return Ok(value[0]),
+            Err(error) => last_error = error,
+        }
+    }
+    Err(last_error)
+}
+
+#[test]
+#[serial]
+fn fault_plans_exercise_retries_reproducibly() {
+    let mut i2c = MockI2c::new();
+    i2c.set_write_read_response(0x76, &[0xD0], vec![0x60]);
+    i2c.set_fault_plan(
+        FaultPlan::new(0)
+            .with(Trigger::Nth(1), Fault::FailWith(I2cError::DataNack(0x76)))
+            .with(Trigger::Nth(2), Fault::Fail),
+    );
+    assert_eq!(read_register_with_retry(&mut i2c, 0x76, 0xD0), Ok(0x60));
+    assert_eq!(i2c.get_fault_plan().unwrap().get_injected_count(), 2);
+
+    // The same seed fails the same operations
+    let outcomes = |seed| {
+        let mut i2c = MockI2c::new();
+        i2c.set_write_read_response(0x76, &[0xD0], vec![0x60]);
+        i2c.set_fault_plan(FaultPlan::new(seed).with(Trigger::Probability(0.3), Fault::Fail));
+        (0..50)
+            .map(|_| i2c.write_read(0x76, &[0xD0], &mut [0u8; 1]).is_ok())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(outcomes(11), outcomes(11));
+    assert!(outcomes(11).contains(&false));
+
+    // A corrupted reply differs from the clean one by exactly the flipped bit
+    let mut spi = MockSpi::new();
+    spi.set_responder(spi_responder::from_fn(|_| vec![0xA5; 4]));
+    spi.set_fault_plan(FaultPlan::new(5).with(Trigger::Every(2), Fault::FlipBits(1)))
+        .unwrap();
+    let mut clean = [0u8; 4];
+    spi.transfer(&mut clean).unwrap();
+    let mut corrupted = [0u8; 4];
+    spi.transfer(&mut corrupted).unwrap();
+    let flipped: u32 = clean
+        .iter()
+        .zip(&corrupted)
+        .map(|(a, b)| (a ^ b).count_ones())
+        .sum();
+    assert_eq!(clean, [0xA5; 4]);
+    assert_eq!(flipped, 1);
+    assert_eq!(spi.get_transaction_log().len(), 2);
+
+    // A stuck output never changes, whatever the driver writes
+    let mut gpio = MockGpio::new();
+    gpio.set_mode(18, PinMode::Output).unwrap();
+    gpio.set_stuck_at(18, false);
+    gpio.write(18, true).unwrap();
+    assert!(!gpio.read(18).unwrap());
+    assert_eq!(gpio.get_write_count(18), 1);
+
+    // Flipping a read pin inverts it however many flips are asked for
+    gpio.set_input_level(4, true);
+    gpio.set_fault_plan(FaultPlan::new(3).with(Trigger::Always, Fault::FlipBits(2)))
+        .unwrap();
+    assert!(!gpio.read(4).unwrap());
+
+    // I2C errors cannot be injected into other buses
+    let nack = FaultPlan::new(0).with(
+        Trigger::Always,
+        Fault::FailWith(I2cError::AddressNack(0x76)),
+    );
+    assert!(gpio.set_fault_plan(nack.clone()).is_err());
+    assert!(spi.set_fault_plan(nack).is_err());
+}
+
+#[test]
+#[serial]
+fn latency_accounts_bus_time_on_virtual_clock() {
+    let clock = VirtualClock::new();
+
+    // Address and register bytes, then address and data bytes: 36 clocks at 100 kHz
+    let mut i2c = MockI2c::new();
+    i2c.set_write_read_response(0x76, &[0xD0], vec![0x60]);
+    i2c.set_latency(
+        &clock,
+        BusTiming::i2c(100_000).with_overhead(Duration::from_micros(50)),
+    );
+    i2c.write_read(0x76, &[0xD0], &mut [0u8; 1]).unwrap();
+    assert_eq!(i2c.get_bus_time(), Duration::from_micros(410));
+    assert_eq!(clock.now(), Duration::from_micros(410));
+
+    // SPI clocks at whatever speed the driver configured
+    let mut spi = MockSpi::new();
+    spi.set_latency(&clock, BusTiming::spi());
+    spi.configure(&SpiConfig::new(SpiMode::Mode0, 1_000_000))
+        .unwrap();
+    spi.transfer(&mut [0x9F, 0, 0, 0]).unwrap();
+    assert_eq!(spi.get_bus_time(), Duration::from_micros(32));
+
+    // Operations take effect when they complete
+    let mut gpio = MockGpio::new();
+    gpio.set_clock(clock.clone());
+    gpio.set_latency(&clock, BusTiming::fixed(Duration::from_micros(2)));
+    gpio.set_mode(18, PinMode::Output).unwrap();
+    gpio.write(18, true).unwrap();
+    assert_eq!(gpio.get_bus_time(), Duration::from_micros(4));
+    assert_eq!(
+        gpio.get_level_changes().last().unwrap().at,
+        Duration::from_micros(446)
+    );
+    assert_eq!(clock.now(), Duration::from_micros(446));
+}
+
+#[test]
+#[serial]
+fn shared_bus_handles_work_across_threads() {
+    let mut mock = MockI2c::new();
+    mock.set_write_read_response(0x76, &[0xD0], vec![0x60]);
+    mock.set_write_read_response(0x77, &[0xD0], vec![0x58]);
+    let bus = SharedI2c::new(mock);
+
+    let workers: Vec<_> = [(0x76, 0x60), (0x77, 0x58)]
+        .into_iter()
+        .map(|(address, chip_id)| {
+            let mut i2c = bus.clone();
+            std::thread::spawn(move || {
+                for _ in 0..50 {
+                    let mut id = [0u8; 1];
+                    i2c.write_read(address, &[0xD0], &mut id).unwrap();
+                    assert_eq!(id[0], chip_id);
+                }
+            })
+        })
+        .collect();
+    for worker in workers {
+        worker.join().unwrap();
+    }
+
+    // Each write_read went through whole, never split by the other thread
+    let log = bus.controller().get_transaction_log().to_vec();
+    assert_eq!(log.len(), 100);
+    assert!(log.iter().all(|(_, operations)| operations.len() == 2));
+    // Every call locked the bus once, as did reading the log
+    assert_eq!(bus.get_metrics().acquisitions, 101);
+
+    // A handle that finds the bus taken waits, and the wait is counted; the
+    // workers above may have waited on each other, so compare with a snapshot
+    let before = bus.get_metrics();
+    let (locked_tx, locked_rx) = std::sync::mpsc::channel();
+    let holder = {
+        let bus = bus.clone();
+        std::thread::spawn(move || {
+            let _guard = bus.controller();
+            locked_tx.send(()).unwrap();
+            std::thread::sleep(Duration::from_millis(20));
+        })
+    };
+    locked_rx.recv().unwrap();
+    let mut i2c = bus.clone();
+    i2c.write(0x76, &[0xF4, 0x27]).unwrap();
+    holder.join().unwrap();
+    let metrics = bus.get_metrics();
+    assert_eq!(metrics.contended - before.contended, 1);
+    assert!(metrics.wait_time - before.wait_time >= Duration::from_millis(10));
+    assert!(metrics.max_wait >= Duration::from_millis(10));
+
+    // SPI devices on their own threads keep their own chip selects and settings
+    let cs_gpio = std::sync::Arc::new(std::sync::Mutex::new(MockGpio::new()));
+    let spi = SharedSpi::new(MockSpi::new()).with_cs_gpio(cs_gpio.clone());
+    let flash = spi
+        .device(ChipSelect::Hardware(0), SpiConfig::default())
+        .unwrap();
+    let adc = spi
+        .device(ChipSelect::Gpio(5), SpiConfig::new(SpiMode::Mode1, 500_000))
+        .unwrap();
+    let workers: Vec<_> = [flash, adc]
+        .into_iter()
+        .map(|mut device| {
+            std::thread::spawn(move || {
+                for _ in 0..20 {
+                    device.write_read(&[0x9F], &mut [0u8; 3]).unwrap();
+                }
+            })
+        })
+        .collect();
+    for worker in workers {
+        worker.join().unwrap();
+    }
+    let controller = spi.controller();
+    assert_eq!(
+        controller.get_transfers_to(ChipSelect::Hardware(0)).len(),
+        20
+    );
+    assert_eq!(controller.get_transfers_to(ChipSelect::Gpio(5)).len(), 20);
+    // Deasserted once when claimed, then asserted and released per transfer
+    assert_eq!(cs_gpio.lock().unwrap().get_write_count(5), 41);
+}